@@ -9,7 +9,6 @@ use std::fs::File;
 use test::Bencher;
 
 use hlife::Hashlife;
-#[cfg(not(features = "4x4_leaf"))]
 use hlife::global::Pattern;
 
 fn read_file(path: &str) -> io::Result<Vec<u8>> {
@@ -19,7 +18,6 @@ fn read_file(path: &str) -> io::Result<Vec<u8>> {
     Ok(buf)
 }
 
-#[cfg(not(features = "4x4_leaf"))]
 #[ignore]
 #[bench]
 fn bench_global_instances(b: &mut Bencher) {
@@ -37,7 +35,6 @@ fn bench_global_instances(b: &mut Bencher) {
     }
 }
 
-#[cfg(not(features = "4x4_leaf"))]
 fn test_in_out_pair(in_rle: &[u8], out_rle: &[u8], steps: u64) {
     Hashlife::with_new(|hl| {
         let in_block = hl.block_from_bytes(&in_rle).unwrap();
@@ -6,22 +6,53 @@ use std::fs::File;
 use std::io::Read;
 use std::process::exit;
 
-use clap::{Arg, App};
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
 
-use hlife::Hashlife;
-use hlife::global::Pattern;
-use hlife::format::write::format_rle;
+use hlife::{Block, Hashlife, LeafSize, Leaf2x2, Leaf4x4};
+use hlife::format::write::format_pattern_rle;
+use hlife::soup::{SoupSearch, SoupSymmetry};
 
 fn main() {
     let matches = App::new("Itai's Hashlife")
+            .setting(AppSettings::SubcommandsNegateReqs)
             .arg(Arg::with_name("INPUT-FILE")
                     .required(true)
                     .index(1))
             .arg(Arg::with_name("GENERATIONS")
                     .required(true)
                     .index(2))
+            .arg(Arg::with_name("leaf-size")
+                    .long("leaf-size")
+                    .takes_value(true)
+                    .possible_values(&["2", "4", "auto"])
+                    .default_value("auto")
+                    .help("Side length of the leafs of the block tree"))
+            .subcommand(SubCommand::with_name("soup")
+                    .about("Run random soups and count the objects they \
+                        leave behind")
+                    .arg(Arg::with_name("count")
+                            .long("count")
+                            .takes_value(true)
+                            .default_value("100")
+                            .help("Number of soups to run"))
+                    .arg(Arg::with_name("seed")
+                            .long("seed")
+                            .takes_value(true)
+                            .default_value("0")
+                            .help("Seed for generating the soups"))
+                    .arg(Arg::with_name("symmetry")
+                            .long("symmetry")
+                            .takes_value(true)
+                            .possible_values(&["C1", "D2", "D4", "D8"])
+                            .default_value("C1")
+                            .help("Symmetry of the soups")))
             .get_matches();
 
+    if let Some(soup_matches) = matches.subcommand_matches("soup") {
+        soup(soup_matches);
+        return;
+    }
+
     let filename = matches.value_of("INPUT-FILE").expect("internal clap error");
     let gens_string = matches.value_of("GENERATIONS").expect("internal clap\
         error");
@@ -41,13 +72,94 @@ fn main() {
         exit(1);
     });
 
-    Hashlife::with_new(|hl| {
-        let block = hl.block_from_bytes(&rle_buf).unwrap_or_else(|_| {
-            println!("Badly formatted RLE in {}", &filename);
+    let use_4x4 = match matches.value_of("leaf-size") {
+        Some("2") => false,
+        Some("4") => true,
+        _ => Hashlife::with_new(|hl| {
+            let block = parse_or_exit(hl, &rle_buf, filename);
+            is_dense(block)
+        }),
+    };
+
+    if use_4x4 {
+        run::<Leaf4x4>(&rle_buf, filename, gens);
+    } else {
+        run::<Leaf2x2>(&rle_buf, filename, gens);
+    }
+}
+
+fn soup(matches: &ArgMatches) {
+    let parse_u64 = |name: &str| {
+        let string = matches.value_of(name).expect("internal clap error");
+        string.parse::<u64>().unwrap_or_else(|_| {
+            println!("Error: --{} must be a nonnegative integer: {}", name,
+                string);
+            exit(1);
+        })
+    };
+    let count = parse_u64("count");
+    let seed = parse_u64("seed");
+    let symmetry: SoupSymmetry = matches.value_of("symmetry")
+        .expect("internal clap error").parse().expect("internal clap error");
+
+    let search = SoupSearch::new(seed, symmetry);
+    let report = Hashlife::<Leaf2x2>::with_new(|hl| search.run(hl, count));
+    println!("Seed: {}", seed);
+    println!("Symmetry: {}", symmetry);
+    print!("{}", report);
+}
+
+fn run<L: LeafSize>(rle_buf: &[u8], filename: &str, gens: u64) {
+    Hashlife::<L>::with_new_leaf(|hl| {
+        let mut pattern = hl.pattern_from_bytes(rle_buf).unwrap_or_else(|| {
+            println!("Badly formatted RLE in {}", filename);
             exit(1);
         });
-        let mut pattern = Pattern::new(block);
         pattern.step(gens);
-        print!("{}", format_rle(&pattern.block()));
+        print!("{}", format_pattern_rle(&pattern));
     });
 }
+
+fn parse_or_exit<'a, L: LeafSize>(hl: Hashlife<'a, L>, rle_buf: &[u8],
+    filename: &str) -> Block<'a, L> {
+
+    hl.block_from_bytes(rle_buf).map(|(block, _)| block).unwrap_or_else(|_| {
+        println!("Badly formatted RLE in {}", filename);
+        exit(1);
+    })
+}
+
+/// Decide whether a pattern is dense enough to be worth running with 4x4
+/// leafs, which evolve faster but waste memory on 4x4 squares that contain
+/// only a few isolated cells. `block` must use 2x2 leafs.
+fn is_dense(block: Block<Leaf2x2>) -> bool {
+    // Count the non-blank 4x4 squares, and the non-blank 2x2 leafs in them.
+    fn count(block: Block<Leaf2x2>, counts: &mut (u64, u64)) {
+        if block.is_blank() {
+            return;
+        }
+        if let Ok(node) = block.destruct() {
+            if node.node_of_leafs() {
+                counts.0 += 1;
+                for row in &node.corners() {
+                    for leaf in row {
+                        if !leaf.is_blank() {
+                            counts.1 += 1;
+                        }
+                    }
+                }
+            } else {
+                for row in &node.corners() {
+                    for &corner in row {
+                        count(corner, counts);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut counts = (0, 0);
+    count(block, &mut counts);
+    // On average at least two out of four 2x2 leafs are occupied.
+    counts.1 >= 2 * counts.0
+}
@@ -3,17 +3,19 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
 
 use fnv::FnvHasher;
+use num::{BigUint, FromPrimitive, One};
 
-use crate::cache::Cache;
+use crate::cache::{Cache, CloneCache, KeyedCache};
 
 #[cfg(feature = "xor_hasher")]
 use self::xor_hasher::XorHasherBuilder as HashmapState;
 #[cfg(not(feature = "xor_hasher"))]
 use std::collections::hash_map::RandomState as HashmapState;
 
-use crate::leaf::{Leaf, LG_LEAF_SIZE};
+use crate::leaf::{Leaf, LeafSize, LEAF_Y_SHIFT, LEAF_X_SHIFT};
 
 // [Currently these notes are out of date.]
 // NOTE ON OWNERSHIP AND SAFETY:
@@ -27,22 +29,24 @@ use crate::leaf::{Leaf, LG_LEAF_SIZE};
 // unsafe Rust to simulate such a feature, and I'm not convinced my
 // implementation is safe.
 //
-// The type Block<'a> corresponds to a block with all references having
+// The type Block<'a, L> corresponds to a block with all references having
 // lifetime 'a (including all references in blocks that it references,
-// recursively). Since Block<'a> includes cache data with references to
-// other blocks and interior mutability, it is invariant in 'a.
+// recursively). Since Block<'a, L> includes cache data with references to
+// other blocks and interior mutability, it is invariant in 'a. The type
+// parameter `L` is the size of the leafs, see `leaf::LeafSize`.
 
 /// A hashtable with all the block nodes used for a Hashlife computation.
 /// Lifetime parameter indicates the lifetime of all the blocks stored therein;
 /// Note that all the blocks are owned are owned by `CABlockCache`; the nodes
 /// themselves only contain references to one another (with lifetime 'a).
-pub struct CABlockCache<'a> (HashMap<u64, Box<HeapNode<'a>>, HashmapState>);
+pub struct CABlockCache<'a, L: LeafSize> (HashMap<u64, Box<HeapNode<'a, L>>,
+    HashmapState>);
 
 /// Error type for hash collision
 #[derive(Debug)]
 pub struct HashCollision;
 
-impl<'a> CABlockCache<'a> {
+impl<'a, L: LeafSize> CABlockCache<'a, L> {
     /// Create a new `CABlockCache` and pass it to `f`.
     /// This indirect initialization approach is necessary since the
     /// CABlockCache needs to outlive its own lifetime parameter, and a simple
@@ -57,7 +61,7 @@ impl<'a> CABlockCache<'a> {
     /// lifetime parameter, and with such a method it is possible (though
     /// probably a bad idea) to mix nodes owned by by block caches.
     pub fn with_new<F, T>(f: F) -> T
-        where F: for<'b> FnOnce(CABlockCache<'b>) -> T {
+        where F: for<'b> FnOnce(CABlockCache<'b, L>) -> T {
 
         let ca_block_cache = CABlockCache(HashMap::with_hasher(
             HashmapState::default()));
@@ -71,17 +75,27 @@ impl<'a> CABlockCache<'a> {
     /// ====== 
     ///
     /// Panics at a hash collision
-    pub fn node(&mut self, elems: [[Block<'a>; 2]; 2]) -> Node<'a> {
+    pub fn node(&mut self, elems: [[Block<'a, L>; 2]; 2]) -> Node<'a, L> {
         self.node_nopanic(elems).unwrap()
     }
 
+    /// Number of nodes in the cache. Nodes are never removed, so this only
+    /// grows.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     /// Like `node`, but returns a result to handle hash collisions instead of
     /// panicking.
-    pub fn node_nopanic(&mut self, elems: [[Block<'a>; 2]; 2]) ->
-        Result<Node<'a>, HashCollision> {
+    pub fn node_nopanic(&mut self, elems: [[Block<'a, L>; 2]; 2]) ->
+        Result<Node<'a, L>, HashCollision> {
 
         let hash = hash(&elems);
-        let blockref: &HeapNode<'a> = &**self.0.entry(hash).or_insert_with(||
+        let blockref: &HeapNode<'a, L> = &**self.0.entry(hash).or_insert_with(||
             Box::new(HeapNode::from_elems_and_hash(elems, hash)));
         if blockref.corners != elems {
             return Err(HashCollision);
@@ -102,7 +116,7 @@ impl<'a> CABlockCache<'a> {
 }
 
 // Just in case, clear CABlockCache before dropping it.
-impl<'a> Drop for CABlockCache<'a> {
+impl<'a, L: LeafSize> Drop for CABlockCache<'a, L> {
     fn drop(&mut self) {
         for block in self.0.values_mut() {
             block.corners = [[Block::Leaf(0); 2]; 2];
@@ -112,42 +126,125 @@ impl<'a> Drop for CABlockCache<'a> {
 
 // Note: uncertain if default implementation of Debug is right
 #[derive(Debug)]
-pub struct HeapNode<'a> {
+pub struct HeapNode<'a, L: LeafSize> {
     // corners[y][x]
-    corners: [[Block<'a>; 2]; 2],
+    corners: [[Block<'a, L>; 2]; 2],
     hash: u64,
-    evolve: Cache<Block<'a>>,
+    evolve: Cache<Block<'a, L>>,
+    // Results of `evolve::step_pow2`, keyed by the log of the number of steps
+    step: KeyedCache<usize, Block<'a, L>>,
+    population: CloneCache<BigUint>,
+    bounds: CloneCache<Option<Bounds>>,
+    leaf_size: PhantomData<L>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
-pub enum Block<'a> {
-    Node(Node<'a>),
+pub enum Block<'a, L: LeafSize> {
+    Node(Node<'a, L>),
     Leaf(Leaf),
 }
 
-pub type Node<'a> = &'a HeapNode<'a>;
+pub type Node<'a, L> = &'a HeapNode<'a, L>;
+
+/// Smallest rectangle containing all live cells of a block, with coordinates
+/// relative to the north-west corner of the block. The minimums are inclusive
+/// and the maximums are exclusive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bounds {
+    pub x_min: BigUint,
+    pub y_min: BigUint,
+    pub x_max: BigUint,
+    pub y_max: BigUint,
+}
+
+impl Bounds {
+    /// Smallest rectangle containing both `self` and `other` moved by
+    /// (dx, dy).
+    fn union_shifted(self, other: &Bounds, dx: &BigUint, dy: &BigUint) ->
+        Bounds {
 
-impl<'a> HeapNode<'a> {
-    fn from_elems_and_hash(elems: [[Block; 2]; 2], hash: u64) -> HeapNode {
+        use std::cmp::{min, max};
+
+        Bounds {
+            x_min: min(self.x_min, &other.x_min + dx),
+            y_min: min(self.y_min, &other.y_min + dy),
+            x_max: max(self.x_max, &other.x_max + dx),
+            y_max: max(self.y_max, &other.y_max + dy),
+        }
+    }
+}
+
+impl<'a, L: LeafSize> HeapNode<'a, L> {
+    fn from_elems_and_hash(elems: [[Block<'a, L>; 2]; 2], hash: u64) -> Self {
         HeapNode {
             corners: elems,
             hash: hash,
             evolve: Cache::new(),
+            step: KeyedCache::new(),
+            population: CloneCache::new(),
+            bounds: CloneCache::new(),
+            leaf_size: PhantomData,
         }
     }
 
-    pub fn corners(&self) -> &[[Block<'a>; 2]; 2] {
+    pub fn corners(&self) -> &[[Block<'a, L>; 2]; 2] {
         &self.corners
     }
 
-    pub fn evolve_cache(&self) -> &Cache<Block<'a>> {
+    pub fn evolve_cache(&self) -> &Cache<Block<'a, L>> {
         &self.evolve
     }
 
+    pub fn step_cache(&self) -> &KeyedCache<usize, Block<'a, L>> {
+        &self.step
+    }
+
     pub fn lg_size(&self) -> usize {
         self.corners()[0][0].lg_size() + 1
     }
 
+    /// Bounding box of the live cells of the node, memoised like
+    /// `population`.
+    pub fn bounds(&self) -> Option<Bounds> {
+        self.bounds.eval(|| {
+            let half = BigUint::one() << (self.lg_size() - 1);
+            let zero = BigUint::from_u8(0).unwrap();
+            let mut res: Option<Bounds> = None;
+            for (y, row) in self.corners().iter().enumerate() {
+                for (x, corner) in row.iter().enumerate() {
+                    if let Some(b) = corner.bounds() {
+                        let dx = if x == 0 {&zero} else {&half};
+                        let dy = if y == 0 {&zero} else {&half};
+                        res = Some(match res {
+                            None => Bounds {
+                                x_min: b.x_min + dx,
+                                y_min: b.y_min + dy,
+                                x_max: b.x_max + dx,
+                                y_max: b.y_max + dy,
+                            },
+                            Some(res) => res.union_shifted(&b, dx, dy),
+                        });
+                    }
+                }
+            }
+            res
+        })
+    }
+
+    /// Number of live cells in the node, memoised so that it takes time
+    /// proportional to the number of distinct nodes below it.
+    pub fn population(&self) -> BigUint {
+        self.population.eval(|| {
+            let mut res = BigUint::from_u8(0).unwrap();
+            for row in self.corners() {
+                for corner in row {
+                    res += corner.population();
+                }
+            }
+            res
+        })
+    }
+
     pub fn node_of_leafs(&self) -> bool {
         if let Block::Leaf(_) = self.corners()[0][0] {
             true
@@ -157,15 +254,15 @@ impl<'a> HeapNode<'a> {
     }
 }
 
-impl<'a> PartialEq for HeapNode<'a> {
-    fn eq(&self, other: &HeapNode<'a>) -> bool {
+impl<'a, L: LeafSize> PartialEq for HeapNode<'a, L> {
+    fn eq(&self, other: &HeapNode<'a, L>) -> bool {
         self.hash == other.hash
     }
 }
 
-impl<'a> Eq for HeapNode<'a> { }
+impl<'a, L: LeafSize> Eq for HeapNode<'a, L> { }
 
-impl<'a> Hash for HeapNode<'a> {
+impl<'a, L: LeafSize> Hash for HeapNode<'a, L> {
     fn hash<H:Hasher>(&self, state: &mut H) {
         state.write_u64(self.hash);
     }
@@ -178,7 +275,7 @@ fn hash<T: Hash>(t: &T) -> u64 {
     s.finish()
 }
 
-impl<'a> Block<'a> {
+impl<'a, L: LeafSize> Block<'a, L> {
     pub fn unwrap_leaf(&self) -> Leaf {
         if let Block::Leaf(l) = *self {
             l
@@ -187,7 +284,7 @@ impl<'a> Block<'a> {
         }
     }
 
-    pub fn unwrap_node(&self) -> &Node<'a> {
+    pub fn unwrap_node(&self) -> &Node<'a, L> {
         if let Block::Node(ref n) = *self {
             n
         } else {
@@ -197,8 +294,8 @@ impl<'a> Block<'a> {
 
     // Will probably be moved
     pub fn lg_size(&self) -> usize {
-        let mut count = LG_LEAF_SIZE;
-        let mut block: &Block = self;
+        let mut count = L::LG_LEAF_SIZE;
+        let mut block: &Block<'a, L> = self;
         while let Block::Node(n) = *block {
             block = &n.corners()[0][0];
             count += 1;
@@ -208,7 +305,7 @@ impl<'a> Block<'a> {
 
     pub fn lg_size_verified(&self) -> Result<usize, ()> {
         match *self {
-            Block::Leaf(_) => Ok(LG_LEAF_SIZE),
+            Block::Leaf(_) => Ok(L::LG_LEAF_SIZE),
             Block::Node(n) => {
                 let corners = n.corners();
                 let size_m1 = corners[0][0].lg_size_verified()?;
@@ -222,6 +319,42 @@ impl<'a> Block<'a> {
         }
     }
 
+    /// Bounding box of the live cells of the block, or `None` if it is blank.
+    pub fn bounds(&self) -> Option<Bounds> {
+        match *self {
+            Block::Leaf(l) => {
+                let mut res: Option<(usize, usize, usize, usize)> = None;
+                for y in 0..L::LEAF_SIZE {
+                    for x in 0..L::LEAF_SIZE {
+                        if (l >> (y*LEAF_Y_SHIFT + x*LEAF_X_SHIFT)) & 1 == 0 {
+                            continue;
+                        }
+                        res = Some(match res {
+                            None => (x, y, x + 1, y + 1),
+                            Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y),
+                                x1.max(x + 1), y1.max(y + 1)),
+                        });
+                    }
+                }
+                res.map(|(x0, y0, x1, y1)| Bounds {
+                    x_min: BigUint::from_usize(x0).unwrap(),
+                    y_min: BigUint::from_usize(y0).unwrap(),
+                    x_max: BigUint::from_usize(x1).unwrap(),
+                    y_max: BigUint::from_usize(y1).unwrap(),
+                })
+            }
+            Block::Node(n) => n.bounds(),
+        }
+    }
+
+    /// Number of live cells in the block.
+    pub fn population(&self) -> BigUint {
+        match *self {
+            Block::Leaf(l) => BigUint::from_u32(l.count_ones()).unwrap(),
+            Block::Node(n) => n.population(),
+        }
+    }
+
     pub fn is_blank(&self) -> bool {
         match *self {
             Block::Leaf(ref l) => *l == 0,
@@ -235,7 +368,7 @@ impl<'a> Block<'a> {
     }
 }
 
-impl<'a> fmt::Debug for Block<'a> {
+impl<'a, L: LeafSize> fmt::Debug for Block<'a, L> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use crate::format::write::raw_format_rle;
         
@@ -283,43 +416,104 @@ mod xor_hasher {
 
 #[cfg(test)]
 mod test {
-    use crate::leaf::LG_LEAF_SIZE;
+    use crate::leaf::{LeafSize, Leaf2x2};
 
     use super::{CABlockCache, Block};
 
-    #[test]
-    fn test_lg_size() {
-        CABlockCache::with_new(|mut bc| {
+    fn lg_size<L: LeafSize>() {
+        CABlockCache::<L>::with_new(|mut bc| {
             let leaf = Block::Leaf(0x03);
-            assert_eq!(leaf.lg_size(), LG_LEAF_SIZE);
+            assert_eq!(leaf.lg_size(), L::LG_LEAF_SIZE);
             let n = bc.node([[leaf, leaf], [Block::Leaf(0x10), leaf]]);
             let mut block = Block::Node(n);
-            assert_eq!(block.lg_size(), LG_LEAF_SIZE + 1);
+            assert_eq!(block.lg_size(), L::LG_LEAF_SIZE + 1);
             for i in 2..10 {
                 block = Block::Node(bc.node([[block; 2]; 2]));
-                assert_eq!(block.lg_size(), LG_LEAF_SIZE + i);
+                assert_eq!(block.lg_size(), L::LG_LEAF_SIZE + i);
             }
         });
     }
 
-    #[test]
-    fn test_lg_size_verified() {
-        CABlockCache::with_new(|mut bc| {
+    leaf_size_test!(test_lg_size, lg_size);
+
+    fn lg_size_verified<L: LeafSize>() {
+        CABlockCache::<L>::with_new(|mut bc| {
             let leaf = Block::Leaf(0x30);
-            assert_eq!(leaf.lg_size_verified(), Ok(LG_LEAF_SIZE));
+            assert_eq!(leaf.lg_size_verified(), Ok(L::LG_LEAF_SIZE));
             let node1 = Block::Node(bc.node([[leaf; 2]; 2]));
-            assert_eq!(node1.lg_size_verified(), Ok(LG_LEAF_SIZE + 1));
+            assert_eq!(node1.lg_size_verified(), Ok(L::LG_LEAF_SIZE + 1));
             let node2 = Block::Node(bc.node([[node1; 2]; 2]));
-            assert_eq!(node2.lg_size_verified(), Ok(LG_LEAF_SIZE + 2));
+            assert_eq!(node2.lg_size_verified(), Ok(L::LG_LEAF_SIZE + 2));
             let node_err = Block::Node(bc.node([[node1, leaf], [node1,
                 node1]]));
             assert_eq!(node_err.lg_size_verified(), Err(()));
         });
     }
 
+    leaf_size_test!(test_lg_size_verified, lg_size_verified);
+
+    #[test]
+    fn test_population() {
+        use num::{BigUint, FromPrimitive, One};
+
+        CABlockCache::<Leaf2x2>::with_new(|mut bc| {
+            let leaf = Block::Leaf(0x13);
+            assert_eq!(leaf.population(), BigUint::from_u8(3).unwrap());
+            let mut block = Block::Node(bc.node([[leaf, Block::Leaf(0)],
+                [Block::Leaf(0x20), leaf]]));
+            assert_eq!(block.population(), BigUint::from_u8(7).unwrap());
+            // Populations of huge blocks with few distinct nodes
+            for _ in 0..200 {
+                block = Block::Node(bc.node([[block; 2]; 2]));
+            }
+            assert_eq!(block.population(), BigUint::from_u8(7).unwrap() *
+                (BigUint::one() << 400));
+        });
+    }
+
+    #[test]
+    fn test_bounds() {
+        use num::{BigUint, FromPrimitive, One};
+        use super::Bounds;
+
+        let n = |x: usize| BigUint::from_usize(x).unwrap();
+        CABlockCache::<Leaf2x2>::with_new(|mut bc| {
+            let blank = Block::Leaf(0);
+            assert_eq!(blank.bounds(), None);
+            let leaf = Block::Leaf(0x10);
+            assert_eq!(leaf.bounds(), Some(Bounds {x_min: n(0), y_min: n(1),
+                x_max: n(1), y_max: n(2)}));
+            let node = Block::Node(bc.node([[blank, Block::Leaf(0x02)],
+                [leaf, blank]]));
+            assert_eq!(node.bounds(), Some(Bounds {x_min: n(0), y_min: n(0),
+                x_max: n(4), y_max: n(4)}));
+            let mut blank = Block::Node(bc.node([[blank; 2]; 2]));
+            let mut block = Block::Node(bc.node([[Block::Leaf(0); 2], [
+                Block::Leaf(0), leaf]]));
+            let mut expected = Bounds {x_min: n(2), y_min: n(3), x_max: n(3),
+                y_max: n(4)};
+            assert_eq!(block.bounds(), Some(expected.clone()));
+            // Bounds in huge blocks
+            for i in 2..200 {
+                let mut corners = [[blank; 2]; 2];
+                corners[1][1] = block;
+                block = Block::Node(bc.node(corners));
+                blank = Block::Node(bc.node([[blank; 2]; 2]));
+                let half = BigUint::one() << i;
+                expected = Bounds {
+                    x_min: &expected.x_min + &half,
+                    y_min: &expected.y_min + &half,
+                    x_max: &expected.x_max + &half,
+                    y_max: &expected.y_max + &half,
+                };
+                assert_eq!(block.bounds(), Some(expected.clone()));
+            }
+        });
+    }
+
     #[test]
     fn test_blank() {
-        CABlockCache::with_new(|mut bc| {
+        CABlockCache::<Leaf2x2>::with_new(|mut bc| {
             let leaf0 = Block::Leaf(0);
             let leaf1 = Block::Leaf(3);
             assert!(leaf0.is_blank());
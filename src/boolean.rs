@@ -0,0 +1,158 @@
+//! Boolean set operations on blocks, treating a block as the set of its live
+//! cells.
+
+use std::collections::HashMap;
+
+use crate::{Block, Hashlife, Leaf, LeafSize};
+use crate::block::Block as RawBlock;
+use crate::util::make_2x2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoolOp {
+    Union,
+    Intersection,
+    Difference,
+    Xor,
+}
+
+impl BoolOp {
+    fn leaf(self, a: Leaf, b: Leaf) -> Leaf {
+        match self {
+            BoolOp::Union => a | b,
+            BoolOp::Intersection => a & b,
+            BoolOp::Difference => a & !b,
+            BoolOp::Xor => a ^ b,
+        }
+    }
+
+    /// The result of the operation if it can be found without looking inside
+    /// the blocks.
+    fn shortcut<'a, L: LeafSize>(self, hl: &Hashlife<'a, L>, a: RawBlock<'a,
+        L>, b: RawBlock<'a, L>, lg_size: usize) -> Option<RawBlock<'a, L>> {
+
+        let blank = || hl.raw_blank(lg_size);
+        match self {
+            BoolOp::Union if a == b || b.is_blank() => Some(a),
+            BoolOp::Union if a.is_blank() => Some(b),
+            BoolOp::Intersection if a == b => Some(a),
+            BoolOp::Intersection if a.is_blank() || b.is_blank() =>
+                Some(blank()),
+            BoolOp::Difference if a == b || a.is_blank() => Some(blank()),
+            BoolOp::Difference if b.is_blank() => Some(a),
+            BoolOp::Xor if a == b => Some(blank()),
+            BoolOp::Xor if a.is_blank() => Some(b),
+            BoolOp::Xor if b.is_blank() => Some(a),
+            _ => None,
+        }
+    }
+}
+
+// Nodes are hash-consed and hashed by structure, so their cells don't affect
+// their use as keys.
+#[allow(clippy::mutable_key_type)]
+fn raw_bool_op<'a, L: LeafSize>(hl: &Hashlife<'a, L>, op: BoolOp, a:
+    RawBlock<'a, L>, b: RawBlock<'a, L>, lg_size: usize, memo: &mut
+    HashMap<(RawBlock<'a, L>, RawBlock<'a, L>), RawBlock<'a, L>>) ->
+    RawBlock<'a, L> {
+
+    if let Some(res) = op.shortcut(hl, a, b, lg_size) {
+        return res;
+    }
+    match (a, b) {
+        (RawBlock::Leaf(a), RawBlock::Leaf(b)) =>
+            RawBlock::Leaf(op.leaf(a, b)),
+        (RawBlock::Node(na), RawBlock::Node(nb)) => {
+            if let Some(&res) = memo.get(&(a, b)) {
+                return res;
+            }
+            let res = hl.raw_node_block(make_2x2(|i, j|
+                raw_bool_op(hl, op, na.corners()[i][j], nb.corners()[i][j],
+                    lg_size - 1, memo)));
+            memo.insert((a, b), res);
+            res
+        }
+        _ => panic!("Boolean operation on blocks of different sizes"),
+    }
+}
+
+impl<'a, L: LeafSize> Block<'a, L> {
+    /// Apply `op` to two blocks of the same size. The result is memoised for
+    /// each pair of subblocks it encounters and shares structure with its
+    /// operands wherever possible. Panics if the sizes differ.
+    pub fn bool_op(&self, op: BoolOp, other: &Block<'a, L>) -> Block<'a, L> {
+        assert_eq!(self.lg_size(), other.lg_size(),
+            "Boolean operation on blocks of different sizes");
+        let hl = self.hashlife_instance();
+        let raw = raw_bool_op(&hl, op, self.to_raw(), other.to_raw(),
+            self.lg_size(), &mut HashMap::new());
+        hl.block_from_raw_sized(raw, self.lg_size())
+    }
+
+    /// Cells alive in either block.
+    pub fn union(&self, other: &Block<'a, L>) -> Block<'a, L> {
+        self.bool_op(BoolOp::Union, other)
+    }
+
+    /// Cells alive in both blocks.
+    pub fn intersection(&self, other: &Block<'a, L>) -> Block<'a, L> {
+        self.bool_op(BoolOp::Intersection, other)
+    }
+
+    /// Cells alive in `self` but not in `other`.
+    pub fn difference(&self, other: &Block<'a, L>) -> Block<'a, L> {
+        self.bool_op(BoolOp::Difference, other)
+    }
+
+    /// Cells alive in exactly one of the blocks.
+    pub fn xor(&self, other: &Block<'a, L>) -> Block<'a, L> {
+        self.bool_op(BoolOp::Xor, other)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Hashlife, LeafSize};
+
+    fn bool_ops<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            let a = hl.rle("2o$2o$2o$2o!");
+            let b = hl.rle("bo$bo$bo$bo!");
+            let blank = hl.blank(a.lg_size());
+            assert_eq!(a.union(&b), a);
+            assert_eq!(b.union(&a), a);
+            assert_eq!(a.intersection(&b), b);
+            assert_eq!(a.difference(&b), hl.rle("o3b$o$o$o!"));
+            assert_eq!(b.difference(&a), blank);
+            assert_eq!(a.xor(&b), hl.rle("o3b$o$o$o!"));
+            assert_eq!(a.xor(&a), blank);
+
+            let c = hl.rle("15bo$7bo$$3o!");
+            let d = hl.rle("$7bo5$8b2o$8b2o!");
+            let union = c.union(&d);
+            assert_eq!(union.population(), c.population() + d.population()
+                - 1u8);
+            assert_eq!(union.difference(&d), c.difference(&d));
+            assert_eq!(union.xor(&c).xor(&d), c.intersection(&d));
+        });
+    }
+
+    leaf_size_test!(test_bool_ops, bool_ops);
+
+    #[test]
+    fn test_bool_ops_huge() {
+        Hashlife::with_new(|hl| {
+            let a = hl.rle("bo$2bo$3o!");
+            let b = hl.rle("o$o$o$o!");
+            let (mut big_a, mut big_b) = (a, b);
+            for _ in 0..100 {
+                big_a = hl.node_block([[big_a; 2]; 2]);
+                big_b = hl.node_block([[big_b; 2]; 2]);
+            }
+            let mut expected = a.xor(&b);
+            for _ in 0..100 {
+                expected = hl.node_block([[expected; 2]; 2]);
+            }
+            assert_eq!(big_a.xor(&big_b), expected);
+        });
+    }
+}
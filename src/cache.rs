@@ -1,4 +1,4 @@
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 
 #[derive(Debug)]
 pub struct Cache<T:Copy>(Cell<Option<T>>);
@@ -21,6 +21,58 @@ impl<T:Copy> Cache<T> {
     }
 }
 
+/// Like `Cache`, but for values which aren't `Copy`, such as `BigUint`s.
+#[derive(Debug)]
+pub struct CloneCache<T:Clone>(RefCell<Option<T>>);
+
+impl<T:Clone> CloneCache<T> {
+    pub fn new() -> Self {
+        CloneCache(RefCell::new(None))
+    }
+
+    pub fn eval<F>(&self, f: F) -> T
+        where F: FnOnce() -> T {
+        let cached = self.0.borrow().clone();
+        match cached {
+            Some(cached) => cached,
+            None => {
+                // `f` may evaluate other caches, so the cache must not be
+                // borrowed while it runs.
+                let calc = f();
+                *self.0.borrow_mut() = Some(calc.clone());
+                calc
+            }
+        }
+    }
+}
+
+/// Like `Cache`, but stores a separate value for each key `eval` is called
+/// with.
+#[derive(Debug)]
+pub struct KeyedCache<K:Copy+Eq, T:Copy>(RefCell<Vec<(K, T)>>);
+
+impl<K:Copy+Eq, T:Copy> KeyedCache<K, T> {
+    pub fn new() -> Self {
+        KeyedCache(RefCell::new(Vec::new()))
+    }
+
+    pub fn eval<F>(&self, key: K, f: F) -> T
+        where F: FnOnce() -> T {
+        let cached = self.0.borrow().iter().find(|&&(k, _)| k == key)
+            .map(|&(_, v)| v);
+        match cached {
+            Some(cached) => cached,
+            None => {
+                // `f` may evaluate other caches, so the cache must not be
+                // borrowed while it runs.
+                let calc = f();
+                self.0.borrow_mut().push((key, calc));
+                calc
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -36,4 +88,22 @@ mod test {
         assert_eq!(res1, 5);
         assert_eq!(t, 1);
     }
+
+    #[test]
+    fn test_clone_cache() {
+        let cache = CloneCache::new();
+        assert_eq!(cache.eval(|| vec![1, 2]), vec![1, 2]);
+        assert_eq!(cache.eval(|| vec![3]), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_keyed_cache() {
+        let mut t = 0;
+        let cache = KeyedCache::new();
+        assert_eq!(cache.eval(1, || {t += 1; 5}), 5);
+        assert_eq!(cache.eval(2, || {t += 1; 6}), 6);
+        assert_eq!(cache.eval(1, || {t += 1; 7}), 5);
+        assert_eq!(cache.eval(2, || {t += 1; 8}), 6);
+        assert_eq!(t, 2);
+    }
 }
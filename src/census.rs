@@ -0,0 +1,196 @@
+//! Splitting a stabilised pattern into separate objects and counting them,
+//! like apgsearch's census.
+
+use std::collections::{HashMap, HashSet};
+
+use num::BigInt;
+
+use crate::LeafSize;
+use crate::format::apgcode;
+use crate::global::Pattern;
+
+/// How objects which aren't periodic are counted in a census.
+pub const PATHOLOGICAL: &str = "PATHOLOGICAL";
+
+impl<'a, L: LeafSize> Pattern<'a, L> {
+    /// Split the pattern into objects which evolve independently of each
+    /// other over the next `gens` generations.
+    ///
+    /// First the cells which are alive in any of these generations are
+    /// grouped into clusters, where cells at most 2 apart are in the same
+    /// cluster. Since a cell only affects its immediate neighbours, clusters
+    /// with a gap of at least 2 dead cells between them can't interact.
+    /// Then each cluster is split into the pieces in which the cells are
+    /// connected as neighbours, if these pieces evolve the same on their own
+    /// as they do together. This separates, for instance, the blocks of a
+    /// bi-block.
+    pub fn separate(&self, gens: u64) -> Vec<Self> {
+        let mut envelope = self.clone();
+        let mut phase = self.clone();
+        for _ in 0..gens {
+            phase.step(1);
+            envelope = envelope.union(&phase);
+        }
+
+        let live = self.live_cells().collect();
+        let mut objects = Vec::new();
+        for cluster in clusters(envelope.live_cells(), 2) {
+            let object = self.restrict(&live, &cluster);
+            let pieces: Vec<_> = clusters(cluster, 1).iter()
+                .map(|piece| self.restrict(&live, piece))
+                .collect();
+            if pieces.len() > 1 && evolve_together(&object, &pieces, gens) {
+                objects.extend(pieces);
+            } else {
+                objects.push(object);
+            }
+        }
+        objects
+    }
+
+    /// Count the objects of the pattern, as split up by `separate`, by their
+    /// apgcode. Objects which aren't still lifes, oscillators or spaceships
+    /// of period at most `max_period` are counted as `PATHOLOGICAL`.
+    pub fn census(&self, max_period: u64) -> HashMap<String, u64> {
+        let mut census = HashMap::new();
+        for object in self.separate(max_period) {
+            let code = apgcode::encode(&object, max_period)
+                .unwrap_or_else(|| PATHOLOGICAL.to_string());
+            *census.entry(code).or_insert(0) += 1;
+        }
+        census
+    }
+
+    /// The pattern made of the cells among `cells` which are in `live`, the
+    /// live cells of `self`. Takes time proportional to the number of
+    /// `cells` rather than to the population of `self`.
+    fn restrict(&self, live: &HashSet<(BigInt, BigInt)>, cells: &[(BigInt,
+        BigInt)]) -> Self {
+
+        let hl = self.block().hashlife_instance();
+        Pattern::from_cells(hl, cells.iter().filter(|&cell| live.contains(cell))
+            .cloned())
+    }
+}
+
+/// Whether `pieces`, which together make up `object`, evolve the same as
+/// `object` for `gens` generations when each is evolved on its own.
+fn evolve_together<'a, L: LeafSize>(object: &Pattern<'a, L>, pieces:
+    &[Pattern<'a, L>], gens: u64) -> bool {
+
+    let mut object = object.clone();
+    let mut pieces = pieces.to_vec();
+    for _ in 0..gens {
+        object.step(1);
+        for piece in &mut pieces {
+            piece.step(1);
+        }
+        let (first, rest) = pieces.split_first().unwrap();
+        let union = rest.iter().fold(first.clone(), |union, piece|
+            union.union(piece));
+        if union != object {
+            return false;
+        }
+    }
+    true
+}
+
+/// Split `cells` into clusters, where two cells are in the same cluster if
+/// there is a chain of cells between them with consecutive cells at most
+/// `dist` apart horizontally and vertically. The result only depends on the
+/// set of cells.
+fn clusters<I>(cells: I, dist: i32) -> Vec<Vec<(BigInt, BigInt)>>
+    where I: IntoIterator<Item = (BigInt, BigInt)> {
+
+    let mut cells: Vec<_> = cells.into_iter().collect();
+    cells.sort();
+    let mut unvisited: HashSet<_> = cells.iter().cloned().collect();
+
+    let mut res = Vec::new();
+    for start in cells {
+        if !unvisited.remove(&start) {
+            continue;
+        }
+        let mut cluster = vec![start];
+        let mut i = 0;
+        while i < cluster.len() {
+            let (x, y) = cluster[i].clone();
+            for dy in -dist..=dist {
+                for dx in -dist..=dist {
+                    let neighbor = (&x + dx, &y + dy);
+                    if unvisited.remove(&neighbor) {
+                        cluster.push(neighbor);
+                    }
+                }
+            }
+            i += 1;
+        }
+        res.push(cluster);
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use num::BigInt;
+
+    use super::{clusters, PATHOLOGICAL};
+    use crate::{Hashlife, LeafSize};
+    use crate::global::Pattern;
+
+    fn census<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            let census = |rle: &'static str| {
+                let mut res: Vec<_> = Pattern::new(hl.rle(rle)).census(10)
+                    .into_iter().collect();
+                res.sort();
+                res
+            };
+            let count = |pairs: &[(&str, u64)]| pairs.iter()
+                .map(|&(code, n)| (code.to_string(), n))
+                .collect::<Vec<_>>();
+
+            assert_eq!(census("!"), count(&[]));
+            // A block, a blinker and a glider with gaps of 2 or 3 cells
+            assert_eq!(census("2o3b3o3bo$2o10bo$10b3o!"),
+                count(&[("xp2_7", 1), ("xq4_153", 1), ("xs4_33", 1)]));
+            // A bi-block is counted as two blocks
+            assert_eq!(census("2ob2o$2ob2o!"), count(&[("xs4_33", 2)]));
+            // The blocks of a beacon interact.
+            assert_eq!(census("2o$2o$2b2o$2b2o!"), count(&[("xp2_318c", 1)]));
+            // The R-pentomino is still evolving.
+            assert_eq!(census("b2o$2o$bo6$10b2o$10b2o!"),
+                count(&[(PATHOLOGICAL, 1), ("xs4_33", 1)]));
+        });
+    }
+
+    leaf_size_test!(test_census, census);
+
+    #[test]
+    fn test_census_stabilised() {
+        Hashlife::with_new(|hl| {
+            // The T-tetromino becomes a traffic light, which is four
+            // blinkers.
+            let mut pattern = Pattern::new(hl.rle("3o$bo!"));
+            pattern.step(10);
+            let mut expected = HashMap::new();
+            expected.insert("xp2_7".to_string(), 4);
+            assert_eq!(pattern.census(10), expected);
+        });
+    }
+
+    #[test]
+    fn test_clusters() {
+        let cells = |coords: &[(i32, i32)]| coords.iter()
+            .map(|&(x, y)| (BigInt::from(x), BigInt::from(y)))
+            .collect::<Vec<_>>();
+        let all = cells(&[(0, 0), (1, 1), (3, 1), (6, 1), (6, 3)]);
+        assert_eq!(clusters(all.clone(), 1), vec![cells(&[(0, 0), (1, 1)]),
+            cells(&[(3, 1)]), cells(&[(6, 1)]), cells(&[(6, 3)])]);
+        assert_eq!(clusters(all, 2), vec![cells(&[(0, 0), (1, 1), (3, 1)]),
+            cells(&[(6, 1), (6, 3)])]);
+        assert_eq!(clusters(Vec::new(), 2), Vec::<Vec<_>>::new());
+    }
+}
@@ -1,18 +1,11 @@
 // In functions below, `depth` is the depth of *output* block. depth == 0 <=>
 // block is leaf.
 
-use num::{One, FromPrimitive, ToPrimitive, BigUint};
+use num::{One, Zero, FromPrimitive, ToPrimitive, BigUint};
 
 use crate::Hashlife;
 use crate::block::{Block as RawBlock, Node as RawNode};
-use crate::leaf::{
-    Leaf,
-    LG_LEAF_SIZE,
-    LEAF_SIZE,
-    QUARTER_LEAF_MASK,
-    LEAF_Y_SHIFT,
-    LEAF_X_SHIFT,
-};
+use crate::leaf::{Leaf, LeafSize, Leaf2x2, Leaf4x4, LEAF_Y_SHIFT, LEAF_X_SHIFT};
 use crate::util::{make_2x2, make_3x3};
 
 /// A table containing the 2x2 center block after one generation for all
@@ -47,15 +40,16 @@ pub fn mk_small_evolve_cache() -> [u8; 1<<16] {
 /// Given 2^(n+1)x2^(n+1) node `node`, progress it 2^(n-1) generations and
 /// return 2^nx2^n block in the center. This is the main component of the
 /// Hashlife algorithm.
-pub fn evolve<'a>(hl: &Hashlife<'a>, node: RawNode<'a>, depth: usize) ->
-    RawBlock<'a> {
+pub fn evolve<'a, L: LeafSize>(hl: &Hashlife<'a, L>, node: RawNode<'a, L>,
+    depth: usize) -> RawBlock<'a, L> {
 
     let elem = node.corners();
 
     node.evolve_cache().eval(move ||
         if node.node_of_leafs() {
             let elem_leafs = make_2x2(|i, j| elem[i][j].unwrap_leaf());
-            RawBlock::Leaf(evolve_leaf(hl, elem_leafs))
+            RawBlock::Leaf(L::evolve_leaf(hl.small_evolve_cache(),
+                elem_leafs))
         } else {
             let intermediates = make_3x3(|i, j| {
                 // subblock needs to be defined as a separate variable because
@@ -73,8 +67,8 @@ pub fn evolve<'a>(hl: &Hashlife<'a>, node: RawNode<'a>, depth: usize) ->
 
 /// Evolve (3*2^n)x(3*2^n) block (encoded as a 3x3 array of 2^nx2^n blocks)
 /// 2^(n-1) steps and return the 2^nx2^n block in the middle
-fn evolve_finish<'a>(hl: &Hashlife<'a>, parts: [[RawBlock<'a>; 3]; 3], depth:
-    usize) -> RawBlock<'a> {
+fn evolve_finish<'a, L: LeafSize>(hl: &Hashlife<'a, L>, parts: [[RawBlock<'a,
+    L>; 3]; 3], depth: usize) -> RawBlock<'a, L> {
 
     let res_components = make_2x2(|i, j| {
         evolve(hl, hl.raw_node(make_2x2(|y, x| parts[i+y][j+x])), depth - 1)
@@ -86,8 +80,8 @@ fn evolve_finish<'a>(hl: &Hashlife<'a>, parts: [[RawBlock<'a>; 3]; 3], depth:
 /// south and x*2^(n-1) east of the north-west corner.
 ///
 /// Public for use in other modules in this crate; don't rely on it.
-pub fn subblock<'a>(hl: &Hashlife<'a>, node: RawNode<'a>, y: u8, x: u8) ->
-    RawBlock<'a> {
+pub fn subblock<'a, L: LeafSize>(hl: &Hashlife<'a, L>, node: RawNode<'a, L>,
+    y: u8, x: u8) -> RawBlock<'a, L> {
 
     debug_assert!(x < 3 && y < 3);
     let (x, y) = (x as usize, y as usize);
@@ -101,8 +95,8 @@ pub fn subblock<'a>(hl: &Hashlife<'a>, node: RawNode<'a>, y: u8, x: u8) ->
     }
 }
 
-fn subblock_node<'a>(hl: &Hashlife<'a>, node: RawNode<'a>, y: usize, x: usize)
-    -> RawBlock<'a> {
+fn subblock_node<'a, L: LeafSize>(hl: &Hashlife<'a, L>, node: RawNode<'a, L>,
+    y: usize, x: usize) -> RawBlock<'a, L> {
 
     //let (x, y) = (x as usize, y as usize);
     let components = make_2x2(|j, i| {
@@ -113,10 +107,10 @@ fn subblock_node<'a>(hl: &Hashlife<'a>, node: RawNode<'a>, y: usize, x: usize)
     hl.raw_node_block(components)
 }
 
-fn subblock_leaf<'a>(_: &Hashlife<'a>, node: RawNode<'a>, y: usize, x: usize) ->
-    RawBlock<'a> {
+fn subblock_leaf<'a, L: LeafSize>(_: &Hashlife<'a, L>, node: RawNode<'a, L>,
+    y: usize, x: usize) -> RawBlock<'a, L> {
 
-    const HALF_LEAF: usize = LEAF_SIZE / 2;
+    let half_leaf = L::LEAF_SIZE / 2;
 
     let mut output_leaf = 0;
     for j in 0..2 {
@@ -125,33 +119,37 @@ fn subblock_leaf<'a>(_: &Hashlife<'a>, node: RawNode<'a>, y: usize, x: usize) ->
             let xx = i+x;
             debug_assert!(xx < 4 && yy < 4);
             let source_leaf = node.corners()[yy / 2][xx / 2].unwrap_leaf();
-            let source_shift = (yy&1) * HALF_LEAF * LEAF_Y_SHIFT
-                + (xx&1) * HALF_LEAF * LEAF_X_SHIFT;
-            let output_shift = j * HALF_LEAF * LEAF_Y_SHIFT
-                + i * HALF_LEAF * LEAF_X_SHIFT;
-            let cell = QUARTER_LEAF_MASK & (source_leaf >> source_shift);
+            let source_shift = (yy&1) * half_leaf * LEAF_Y_SHIFT
+                + (xx&1) * half_leaf * LEAF_X_SHIFT;
+            let output_shift = j * half_leaf * LEAF_Y_SHIFT
+                + i * half_leaf * LEAF_X_SHIFT;
+            let cell = L::QUARTER_LEAF_MASK & (source_leaf >> source_shift);
             output_leaf |= cell << output_shift;
         }
     }
     RawBlock::Leaf(output_leaf)
 }
 
-/// `evolve` specialized to when the corners are all leafs.
-#[cfg(not(feature = "4x4_leaf"))]
+/// `evolve` specialized to when the corners are all 2x2 leafs.
+///
+/// Public for use in `leaf`; don't rely on it.
 #[inline]
-fn evolve_leaf(hl: &Hashlife, leafs: [[Leaf; 2]; 2]) -> Leaf {
-    debug_assert_eq!(LEAF_SIZE, 2);
+pub fn evolve_leaf_2x2(small_evolve_cache: &[u8; 1<<16], leafs: [[Leaf; 2]; 2])
+    -> Leaf {
+
     let entry = leafs[0][0] as usize
         + ((leafs[0][1] as usize) << 2)
         + ((leafs[1][0] as usize) << 8)
         + ((leafs[1][1] as usize) << 10);
-    hl.small_evolve_cache()[entry]
+    Leaf::from(small_evolve_cache[entry])
 }
 
-/// `evolve` specialized to when the corners are all leafs.
-#[cfg(feature = "4x4_leaf")]
-fn evolve_leaf(hl: &Hashlife, leafs: [[Leaf; 2]; 2]) -> Leaf {
-    let small_evolve_cache = hl.small_evolve_cache();
+/// `evolve` specialized to when the corners are all 4x4 leafs.
+///
+/// Public for use in `leaf`; don't rely on it.
+pub fn evolve_leaf_4x4(small_evolve_cache: &[u8; 1<<16], leafs: [[Leaf; 2]; 2])
+    -> Leaf {
+
     let e4x4 = |l: Leaf| small_evolve_cache[l as usize] as Leaf;
 
     let nw = leafs[0][0];
@@ -181,15 +179,17 @@ fn evolve_leaf(hl: &Hashlife, leafs: [[Leaf; 2]; 2]) -> Leaf {
     eenw | eene << 2 | eesw << 8 | eese << 10
 }
 
-#[cfg(not(feature = "4x4_leaf"))]
-fn leaf_step(_: &Hashlife, leafs: [[Leaf; 2]; 2], nstep: u64) -> Leaf {
+/// Public for use in `leaf`; don't rely on it.
+pub fn leaf_step_2x2(_: &[u8; 1<<16], leafs: [[Leaf; 2]; 2], nstep: u64) ->
+    Leaf {
+
     // Equivalent to (nstep < LEAF_SIZE/2)
     debug_assert_eq!(nstep, 0);
     let mut res = 0;
-    for y in 0..2 {
-        for x in 0..2 {
-            let quarter = (leafs[y][x] >> ((1-y) * LEAF_Y_SHIFT + (1-x) * LEAF_X_SHIFT)) &
-                QUARTER_LEAF_MASK;
+    for (y, row) in leafs.iter().enumerate() {
+        for (x, &leaf) in row.iter().enumerate() {
+            let quarter = (leaf >> ((1-y) * LEAF_Y_SHIFT + (1-x) * LEAF_X_SHIFT)) &
+                Leaf2x2::QUARTER_LEAF_MASK;
             res |= quarter << (y * LEAF_Y_SHIFT + x * LEAF_X_SHIFT);
         }
     }
@@ -197,24 +197,25 @@ fn leaf_step(_: &Hashlife, leafs: [[Leaf; 2]; 2], nstep: u64) -> Leaf {
 }
 
 // TODO: This is a mess. Fix it.
-#[cfg(feature = "4x4_leaf")]
-fn leaf_step(hl: &Hashlife, leafs: [[Leaf; 2]; 2], nstep: u64) -> Leaf {
+/// Public for use in `leaf`; don't rely on it.
+pub fn leaf_step_4x4(small_evolve_cache: &[u8; 1<<16], leafs: [[Leaf; 2]; 2],
+    nstep: u64) -> Leaf {
+
     // LEAF_SIZE / 2 == 2
-    debug_assert!(nstep < (LEAF_SIZE / 2) as u64);
+    debug_assert!(nstep < (Leaf4x4::LEAF_SIZE / 2) as u64);
 
     if nstep == 0 {
         let mut res = 0;
-        for y in 0..2 {
-            for x in 0..2 {
-                let quarter = (leafs[y][x] >> (2 * (1-y) * LEAF_Y_SHIFT + 2 *
-                    (1-x) * LEAF_X_SHIFT)) & QUARTER_LEAF_MASK;
+        for (y, row) in leafs.iter().enumerate() {
+            for (x, &leaf) in row.iter().enumerate() {
+                let quarter = (leaf >> (2 * (1-y) * LEAF_Y_SHIFT + 2 *
+                    (1-x) * LEAF_X_SHIFT)) & Leaf4x4::QUARTER_LEAF_MASK;
                 res |= quarter << (2 * y * LEAF_Y_SHIFT + 2 * x * LEAF_X_SHIFT);
             }
         }
         res
     } else {
-        let small_evolve_cache = hl.small_evolve_cache();
-        let e4x4 = |l: Leaf| small_evolve_cache[l as usize] as Leaf;
+        let e4x4 = |l: Leaf| Leaf::from(small_evolve_cache[l as usize]);
 
         /*
         for y in 0..2 {
@@ -228,8 +229,8 @@ fn leaf_step(hl: &Hashlife, leafs: [[Leaf; 2]; 2], nstep: u64) -> Leaf {
 
         let mut collected: u64 = 0;
         debug_assert!(LEAF_X_SHIFT == 1);
-        for y in 0..2 {
-            for x in 0..2 {
+        for (y, row) in leafs.iter().enumerate() {
+            for (x, &leaf) in row.iter().enumerate() {
                 /*
                 let ny = 1 - y; let nx = 1 - x;
                 let around =
@@ -237,8 +238,7 @@ fn leaf_step(hl: &Hashlife, leafs: [[Leaf; 2]; 2], nstep: u64) -> Leaf {
                         & 0x0777) << (y * LEAF_Y_SHIFT + x * LEAF_X_SHIFT))
                     | (((leafs[y][nx] >> 
                 */
-                let leaf = leafs[y][x];
-                for i in 0..LEAF_SIZE {
+                for i in 0..Leaf4x4::LEAF_SIZE {
                     let row = (leaf >> (i * LEAF_Y_SHIFT)) & 0xf;
                     collected |= (row as u64) << (32 * y + 4 * x + 8 * i);
                 }
@@ -263,51 +263,61 @@ fn leaf_step(hl: &Hashlife, leafs: [[Leaf; 2]; 2], nstep: u64) -> Leaf {
     }
 }
 
-#[cfg(not(feature = "4x4_leaf"))]
-pub fn step_pow2<'a>(hl: &Hashlife<'a>, node: RawNode<'a>, lognsteps: usize) ->
-    RawBlock<'a> {
+/// Return sidelength 2^(n-1) block at the center of node after it evolved
+/// for 2^lognsteps steps. Like `evolve`, the result is memoised in the node,
+/// so repeatedly stepping by the same power of two reuses earlier work.
+pub fn step_pow2<'a, L: LeafSize>(hl: &Hashlife<'a, L>, node: RawNode<'a, L>,
+    lognsteps: usize) -> RawBlock<'a, L> {
 
     assert!(lognsteps <= node.lg_size() - 2);
 
     if lognsteps == node.lg_size() - 2 {
-        hl.raw_evolve(node)
-    } else {
-        let parts = make_3x3(|i, j| {
-            subblock(hl,
-                subblock(hl, node, i as u8, j as u8).unwrap_node(), 1, 1)
-        });
-
-        hl.raw_node_block(make_2x2(|y, x| {
-            let around = hl.raw_node(make_2x2(|i, j| parts[y+i][x+j]));
-            step_pow2(hl, around, lognsteps)
-        }))
+        return hl.raw_evolve(node);
     }
-}
 
-#[cfg(feature = "4x4_leaf")]
-pub fn step_pow2<'a>(hl: &Hashlife<'a>, node: RawNode<'a>, lognsteps: usize) ->
-    RawBlock<'a> {
+    node.step_cache().eval(lognsteps, move ||
+        if node.node_of_leafs() {
+            let corners = make_2x2(|y, x| node.corners()[y][x].unwrap_leaf());
+            RawBlock::Leaf(L::leaf_step(hl.small_evolve_cache(), corners, 1 <<
+                lognsteps))
+        } else {
+            let parts = make_3x3(|i, j| {
+                subblock(hl,
+                    subblock(hl, node, i as u8, j as u8).unwrap_node(), 1, 1)
+            });
 
-    unimplemented!()
+            hl.raw_node_block(make_2x2(|y, x| {
+                let around = hl.raw_node(make_2x2(|i, j| parts[y+i][x+j]));
+                step_pow2(hl, around, lognsteps)
+            }))
+        }
+    )
 }
 
-pub fn step<'a>(hl: &Hashlife<'a>, node: RawNode<'a>, depth: usize, nsteps: u64)
-    -> RawBlock<'a> {
+pub fn step<'a, L: LeafSize>(hl: &Hashlife<'a, L>, node: RawNode<'a, L>, depth:
+    usize, nsteps: u64) -> RawBlock<'a, L> {
     step_u(hl, node, depth, &BigUint::from_u64(nsteps).unwrap())
 }
 
-pub fn step_u<'a>(hl: &Hashlife<'a>, node: RawNode<'a>, depth: usize, nsteps:
-    &BigUint) -> RawBlock<'a> {
+pub fn step_u<'a, L: LeafSize>(hl: &Hashlife<'a, L>, node: RawNode<'a, L>,
+    depth: usize, nsteps: &BigUint) -> RawBlock<'a, L> {
 
     // Make more efficient?
-    debug_assert!(*nsteps < BigUint::one() << (depth + LG_LEAF_SIZE - 1));
-
-    if depth == 0 {
+    debug_assert!(*nsteps < BigUint::one() << (depth + L::LG_LEAF_SIZE - 1));
+
+    if nsteps.is_zero() {
+        subblock(hl, node, 1, 1)
+    } else if nsteps.count_ones() == 1 {
+        // Powers of two go through `step_pow2` so that they are memoised.
+        let lognsteps = nsteps.trailing_zeros().unwrap() as usize;
+        step_pow2(hl, node, lognsteps)
+    } else if depth == 0 {
         let corners = make_2x2(|y, x| node.corners()[y][x].unwrap_leaf());
-        RawBlock::Leaf(leaf_step(hl, corners, nsteps.to_u64().unwrap()))
+        RawBlock::Leaf(L::leaf_step(hl.small_evolve_cache(), corners,
+            nsteps.to_u64().unwrap()))
     } else {
         // Highest-order shift
-        let ho_shift = depth + LG_LEAF_SIZE - 2;
+        let ho_shift = depth + L::LG_LEAF_SIZE - 2;
         // Highest-order bit
         let ho_bit = nsteps >> ho_shift;
         // Remaining bits
@@ -332,7 +342,9 @@ pub fn step_u<'a>(hl: &Hashlife<'a>, node: RawNode<'a>, depth: usize, nsteps:
 
 #[cfg(test)]
 mod test {
-    use crate::Hashlife;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    use crate::{Hashlife, LeafSize};
 
     use super::mk_small_evolve_cache;
 
@@ -352,8 +364,7 @@ mod test {
         )
     }
 
-    #[test]
-    fn test_evolve() {
+    fn evolve<L: LeafSize>() {
         const INPUT_RLES: &'static [&'static str] = &[
             "bbo$boo$bbo!",
             "x = 8, y = 8, rule = B3/S23\n\
@@ -365,7 +376,7 @@ mod test {
             o$b2o$o$o!"
         ];
 
-        Hashlife::with_new(|hl| {
+        Hashlife::<L>::with_new_leaf(|hl| {
             for (input_rle, output_rle) in INPUT_RLES.iter()
                                                      .zip(OUTPUT_RLES.iter()) {
                 let input = hl.rle(input_rle);
@@ -381,7 +392,8 @@ mod test {
         });
     }
 
-    #[cfg(not(feature = "4x4_leaf"))]
+    leaf_size_test!(test_evolve, evolve);
+
     #[test]
     fn test_step_pow2() {
         Hashlife::with_new(|hl| {
@@ -393,7 +405,73 @@ mod test {
         });
     }
 
-    #[cfg(not(feature = "4x4_leaf"))]
+    /// Reference implementation of Life on a square matrix, with the cells
+    /// outside the matrix taken to be dead.
+    fn naive_step(cells: &[Vec<bool>], nsteps: usize) -> Vec<Vec<bool>> {
+        let size = cells.len() as isize;
+        let mut cells = cells.to_vec();
+        for _ in 0..nsteps {
+            cells = (0..size).map(|y| (0..size).map(|x| {
+                let mut count = 0;
+                for yy in y-1..y+2 {
+                    for xx in x-1..x+2 {
+                        if 0 <= xx && xx < size && 0 <= yy && yy < size
+                            && (xx, yy) != (x, y)
+                            && cells[yy as usize][xx as usize] {
+                            count += 1;
+                        }
+                    }
+                }
+                count == 3 || (count == 2 && cells[y as usize][x as usize])
+            }).collect()).collect();
+        }
+        cells
+    }
+
+    fn matrix_to_rle(cells: &[Vec<bool>]) -> String {
+        let rows: Vec<String> = cells.iter().map(|row| row.iter().map(|&c|
+            if c {'o'} else {'b'}).collect()).collect();
+        rows.join("$") + "!"
+    }
+
+    fn step_matches_naive<L: LeafSize>() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        Hashlife::<L>::with_new_leaf(|hl| {
+            for _ in 0..4 {
+                let cells: Vec<Vec<bool>> = (0..32).map(|_| (0..32).map(|_|
+                    rng.gen()).collect()).collect();
+                let n = hl.block_from_bytes(matrix_to_rle(&cells).as_bytes())
+                    .unwrap().0.unwrap_node();
+                for nsteps in 0..9 {
+                    let center: Vec<Vec<bool>> = naive_step(&cells, nsteps)
+                        [8..24].iter().map(|row| row[8..24].to_vec())
+                        .collect();
+                    let expected = hl.block_from_bytes(matrix_to_rle(&center)
+                        .as_bytes()).unwrap().0;
+                    if nsteps < 8 {
+                        assert_eq!(hl.step(n, nsteps as u64), expected);
+                    }
+                    if nsteps.is_power_of_two() {
+                        let lognsteps = nsteps.trailing_zeros() as usize;
+                        assert_eq!(hl.step_pow2(n, lognsteps), expected);
+                    }
+                }
+            }
+        });
+    }
+
+    leaf_size_test!(test_step_matches_naive, step_matches_naive);
+
+    #[test]
+    fn test_step_pow2_memoised() {
+        Hashlife::with_new(|hl| {
+            let b = hl.raw_rle("2$6o!");
+            let n = b.unwrap_node();
+            let res = hl.raw_step_pow2(n, 0);
+            assert_eq!(n.step_cache().eval(0, || panic!("Not memoised")), res);
+        });
+    }
+
     #[test]
     fn test_subblock_0() {
         Hashlife::with_new(|hl| {
@@ -412,9 +490,8 @@ mod test {
         });
     }
 
-    #[test]
-    fn test_subblock_1() {
-        Hashlife::with_new(|hl| {
+    fn subblock_1<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
             let b = hl.raw_rle("2$7o!");
             let n = b.unwrap_node();
 
@@ -424,13 +501,16 @@ mod test {
         });
     }
 
-    #[test]
-    fn test_step() {
-        Hashlife::with_new(|hl| {
+    leaf_size_test!(test_subblock_1, subblock_1);
+
+    fn step<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
             assert_eq!(hl.step(hl.rle("3$2b3o!").unwrap_node(), 1),
                 hl.rle("bo$bo$bo!"));
             assert_eq!(hl.step(hl.rle("8o$8o$8o$8o$8o$8o$8o$8o!").unwrap_node(),
                 1), hl.rle("x=4,y=5,rule=B3/S34\n4b!"));
         });
     }
+
+    leaf_size_test!(test_step, step);
 }
@@ -0,0 +1,270 @@
+//! Names of objects as apgcodes, as used by apgsearch and Catagolue. These
+//! are made of a prefix giving the type of the object followed by the
+//! object in extended Wechsler format, for example `xs4_33` for the block,
+//! `xp2_7` for the blinker and `xq4_153` for the glider.
+
+use num::{BigInt, BigUint, ToPrimitive, Zero};
+
+use crate::{Block, Hashlife, LeafSize, Symmetry};
+use crate::global::Pattern;
+
+/// Objects with a phase wider or taller than this are only described by
+/// their type, as in `ov_p30`.
+const MAX_SIDE: usize = 40;
+
+/// Codes of objects wider or taller than this are rejected by `decode`, so
+/// that a short code can't ask for a huge block.
+const MAX_DECODED_SIDE: usize = 1 << 10;
+
+const DIGITS: &[u8; 36] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+/// The apgcode of `pattern` if it is a still life (`xs` followed by the
+/// population), an oscillator (`xp` followed by the period) or a spaceship
+/// (`xq` followed by the period) of period at most `max_period`, and `None`
+/// otherwise. The code is the shortest, and then alphabetically first, over
+/// all phases and orientations of the object.
+pub fn encode<L: LeafSize>(pattern: &Pattern<L>, max_period: u64) ->
+    Option<String> {
+
+    let (kind, period) = match pattern.detect_period(max_period) {
+        Some(1) => ("s", 1),
+        Some(period) => ("p", period),
+        None => ("q", pattern.detect_spaceship(max_period)?.period),
+    };
+    let number = if kind == "s" {pattern.population()} else {
+        BigUint::from(period)};
+
+    let mut best: Option<String> = None;
+    let mut phase = pattern.clone();
+    for _ in 0..period {
+        for &sym in &Symmetry::ALL {
+            let wechsler = match wechsler(&phase.transform(sym)) {
+                Some(wechsler) => wechsler,
+                None => return Some(format!("ov_{}{}", kind, number)),
+            };
+            let better = match best {
+                Some(ref best) => (wechsler.len(), &wechsler)
+                    < (best.len(), best),
+                None => true,
+            };
+            if better {
+                best = Some(wechsler);
+            }
+        }
+        phase.step(1);
+    }
+    Some(format!("x{}{}_{}", kind, number, best.unwrap()))
+}
+
+/// The extended Wechsler format of the pattern as it is oriented, or `None`
+/// if it's too big. The pattern is cut into strips 5 cells tall, separated
+/// by `z`, and each column of a strip is written as a digit in base 32 whose
+/// bits are the cells from top to bottom. Runs of blank columns are
+/// abbreviated by `0`, `w`, `x` and `y` followed by the run length minus 4,
+/// and are left out at the end of a strip.
+fn wechsler<L: LeafSize>(pattern: &Pattern<L>) -> Option<String> {
+    let bbox = match pattern.bounding_box() {
+        Some(bbox) => bbox,
+        None => return Some("0".to_string()),
+    };
+    let width = bbox.width.to_usize().filter(|&w| w <= MAX_SIDE)?;
+    let height = bbox.height.to_usize().filter(|&h| h <= MAX_SIDE)?;
+
+    // columns[strip][x]. `usize::div_ceil` would need Rust 1.73.
+    #[allow(clippy::manual_div_ceil)]
+    let mut columns = vec![vec![0; width]; (height + 4) / 5];
+    for (x, y) in pattern.live_cells() {
+        let x = (x - &bbox.x).to_usize().unwrap();
+        let y = (y - &bbox.y).to_usize().unwrap();
+        columns[y / 5][x] |= 1 << (y % 5);
+    }
+
+    let mut res = String::new();
+    for (i, strip) in columns.iter().enumerate() {
+        if i > 0 {
+            res.push('z');
+        }
+        let mut zeros = 0;
+        for &column in strip {
+            if column == 0 {
+                zeros += 1;
+                continue;
+            }
+            while zeros > 0 {
+                let run = zeros.min(DIGITS.len() + 3);
+                match run {
+                    1 => res.push('0'),
+                    2 => res.push('w'),
+                    3 => res.push('x'),
+                    _ => {
+                        res.push('y');
+                        res.push(DIGITS[run - 4] as char);
+                    }
+                }
+                zeros -= run;
+            }
+            res.push(DIGITS[column] as char);
+        }
+    }
+    Some(res)
+}
+
+/// Decode an apgcode of a still life, oscillator or spaceship into a block
+/// with the object at its north-west corner. Returns `None` if the code is
+/// malformed, if the population in the code of a still life is wrong, if the
+/// object is wider or taller than `MAX_DECODED_SIDE`, or if the code doesn't
+/// contain the object, as in `ov_p30`.
+pub fn decode<'a, L: LeafSize>(hl: &Hashlife<'a, L>, code: &str) ->
+    Option<Block<'a, L>> {
+
+    let (prefix, wechsler) = split_code(code)?;
+    let cells = wechsler_cells(wechsler)?;
+    if let Some(population) = prefix.strip_prefix("xs") {
+        if population.parse::<usize>().ok()? != cells.len() {
+            return None;
+        }
+    }
+
+    let side = cells.iter().map(|&(x, y)| x.max(y) + 1).max().unwrap_or(1);
+    let mut depth = 0;
+    while L::LEAF_SIZE << depth < side {
+        depth += 1;
+    }
+    let pattern = Pattern::from_cells(*hl, cells.into_iter()
+        .map(|(x, y)| (BigInt::from(x), BigInt::from(y))));
+    Some(pattern.block_at(&BigInt::zero(), &BigInt::zero(), L::LG_LEAF_SIZE
+        + depth))
+}
+
+/// Split an apgcode into its prefix, such as `xp2`, and the extended
+/// Wechsler format of the object.
+fn split_code(code: &str) -> Option<(&str, &str)> {
+    let underscore = code.find('_')?;
+    let (prefix, wechsler) = (&code[..underscore], &code[underscore + 1..]);
+    let number = ["xs", "xp", "xq"].iter()
+        .find_map(|kind| prefix.strip_prefix(kind))?;
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((prefix, wechsler))
+}
+
+/// The (x, y) coordinates of the live cells of an object in extended
+/// Wechsler format. See `wechsler`. Returns `None` if the object is wider or
+/// taller than `MAX_DECODED_SIDE`.
+fn wechsler_cells(wechsler: &str) -> Option<Vec<(usize, usize)>> {
+    let digit = |c: u8| DIGITS.iter().position(|&d| d == c);
+    let mut cells = Vec::new();
+    let mut bytes = wechsler.bytes();
+    let (mut x, mut strip) = (0, 0);
+    while let Some(c) = bytes.next() {
+        match c {
+            b'z' => {
+                x = 0;
+                strip += 1;
+                if strip * 5 >= MAX_DECODED_SIDE {
+                    return None;
+                }
+            }
+            b'w' => x += 2,
+            b'x' => x += 3,
+            b'y' => x += 4 + digit(bytes.next()?)?,
+            _ => {
+                let column = digit(c)?;
+                if column >= 32 || x >= MAX_DECODED_SIDE {
+                    return None;
+                }
+                for bit in 0..5 {
+                    if column & 1 << bit != 0 {
+                        cells.push((x, strip * 5 + bit));
+                    }
+                }
+                x += 1;
+            }
+        }
+    }
+    Some(cells)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode};
+    use crate::{Hashlife, LeafSize, Symmetry};
+    use crate::global::Pattern;
+
+    fn encode_objects<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            let code = |rle: &'static str| encode(&Pattern::new(hl.rle(rle)),
+                100);
+            assert_eq!(code("2o$2o!"), Some("xs4_33".to_string()));
+            assert_eq!(code("3o!"), Some("xp2_7".to_string()));
+            assert_eq!(code("bo$2bo$3o!"), Some("xq4_153".to_string()));
+            assert_eq!(code("b2o$o2bo$b2o!"), Some("xs6_696".to_string()));
+            assert_eq!(code("bo$obo$2o!"), Some("xs5_253".to_string()));
+            assert_eq!(code("bo2bo$o$o3bo$4o!"),
+                Some("xq4_6frc".to_string()));
+            assert_eq!(code("2o$2o$2b2o$2b2o!"), Some("xp2_318c".to_string()));
+            // The R-pentomino isn't periodic.
+            assert_eq!(code("b2o$2o$bo!"), None);
+
+            let glider = Pattern::new(hl.rle("bo$2bo$3o!"));
+            for &sym in &Symmetry::ALL {
+                assert_eq!(encode(&glider.transform(sym), 10),
+                    Some("xq4_153".to_string()));
+            }
+        });
+    }
+
+    leaf_size_test!(test_encode, encode_objects);
+
+    fn decode_objects<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            assert_eq!(decode(&hl, "xs4_33"), Some(hl.rle("2o$2o!")));
+            assert_eq!(decode(&hl, "xq4_153"), Some(hl.rle("3o$2bo$bo!")));
+            assert_eq!(decode(&hl, "xs0_0"), Some(hl.rle("!")));
+            assert_eq!(decode(&hl, "xs8_33y833"),
+                Some(hl.rle("2o12b2o$2o12b2o!")));
+            assert_eq!(decode(&hl, "xs12_33x33x33"),
+                Some(hl.rle("2o3b2o3b2o$2o3b2o3b2o!")));
+            assert_eq!(decode(&hl, "xp2_7zw1"), Some(hl.rle("o$o$o$$$2bo!")));
+
+            for &code in &["xs6_696", "xs5_253", "xq4_6frc", "xp2_318c",
+                "xp2_7", "xs12_33x33x33"] {
+                let object = Pattern::new(decode(&hl, code).unwrap());
+                assert_eq!(encode(&object, 10), Some(code.to_string()));
+            }
+
+            for &code in &["", "xs4", "33", "xs_33", "xr4_33", "xs4_3A",
+                "xs4_y", "xs5_33", "ov_p30", "xs4_33_"] {
+                assert_eq!(decode(&hl, code), None, "{}", code);
+            }
+
+            // Short codes of huge objects
+            let tall = format!("xp2_1{}1", "z".repeat(100000));
+            assert_eq!(decode(&hl, &tall), None);
+            let wide = format!("xp2_1{}1", "yz".repeat(100000));
+            assert_eq!(decode(&hl, &wide), None);
+            let code = format!("xs2_1{}1", "yz".repeat(20));
+            assert_eq!(decode(&hl, &code).map(|block| block.population()),
+                Some(2u8.into()));
+        });
+    }
+
+    leaf_size_test!(test_decode, decode_objects);
+
+    #[test]
+    fn test_encode_gaps() {
+        Hashlife::with_new(|hl| {
+            // Two blocks 12 cells apart
+            let code = encode(&Pattern::new(hl.rle("2o12b2o$2o12b2o!")), 10);
+            assert_eq!(code, Some("xs8_33y833".to_string()));
+            // Three blocks in a column are encoded lying down, since `x`
+            // sorts before `z`.
+            let code = encode(&Pattern::new(hl.rle("2o$2o4$2o$2o4$2o$2o!")),
+                10);
+            assert_eq!(code, Some("xs12_33x33x33".to_string()));
+            let code = encode(&Pattern::new(hl.rle("2o43b2o$2o43b2o!")), 10);
+            assert_eq!(code, Some("ov_s8".to_string()));
+        });
+    }
+}
@@ -1,49 +1,135 @@
+//! Make a block out of the lines of a macrocell file.
+
 use crate::{Block, Hashlife};
-use crate::leaf::LG_LEAF_SIZE;
-use crate::util::try_make_2x2;
+use crate::leaf::LeafSize;
+use crate::util::{make_2x2, try_make_2x2};
 
-use super::build_rle::block_from_matrix;
+use super::build_rle::{block_from_matrix, block_from_rows};
 use super::parse::{State, MCLine, MCLeaf, MCNode};
 
-pub fn build_mc<'a>(hl: &Hashlife<'a>, mclines: &[MCLine]) -> Result<Block<'a>,
-    ()> {
+/// Largest lg size of a node, so that a bad depth fails instead of building a
+/// blank block of absurd depth.
+const MAX_LG_SIZE: usize = 1 << 12;
+
+/// The block described by a line, which is kept as rows of cells if it is
+/// smaller than a leaf.
+#[derive(Clone)]
+enum Entry<'a, L: LeafSize> {
+    Cells(Vec<Vec<State>>),
+    Block(Block<'a, L>),
+}
+
+/// Build the block described by the last line. Fails if a node refers to a
+/// later line or to a block of the wrong size, if a node is larger than
+/// 2^`MAX_LG_SIZE` by 2^`MAX_LG_SIZE`, or if a cell has a state other than 0
+/// or 1.
+pub fn build_mc<'a, L: LeafSize>(hl: &Hashlife<'a, L>, mclines: &[MCLine]) ->
+    Result<Block<'a, L>, ()> {
 
+    // The entry of each line with its lg size
     let mut table = Vec::new();
 
     for line in mclines {
-        let new_block = match *line {
-            MCLine::Leaf(ref leaf) => build_mc_leaf(hl, leaf),
-            MCLine::Node(MCNode(d, b0, b1, b2, b3)) => {
-                let elems = try_make_2x2(|i, j| {
-                    let index: usize = match (i, j)
-                        {(0,0) => b0, (0,1) => b1, (1,0) => b2, (1,1) => b3,
-                         _ => unreachable!()};
-                    if index == 0 {
-                        Ok(hl.blank(d-1))
-                    } else {
-                        Ok(*table.get(index-1).ok_or(())?)
+        let new_entry = match *line {
+            MCLine::Leaf(ref leaf) => (3, build_mc_leaf(hl, leaf)?),
+            MCLine::Node(MCNode(d, b0, b1, b2, b3)) =>
+                (d, build_mc_node(hl, &table, d, [[b0, b1], [b2, b3]])?),
+        };
+        table.push(new_entry);
+    }
+    // Printing the blocks themselves would expand them, which is hopeless
+    // for large patterns.
+    debug!("Table: {} blocks", table.len());
+    match table.pop() {
+        Some((_, Entry::Block(block))) => Ok(block),
+        Some((_, Entry::Cells(rows))) => Ok(block_from_rows(hl, rows)),
+        None => Err(()),
+    }
+}
+
+fn build_mc_leaf<'a, L: LeafSize>(hl: &Hashlife<'a, L>, leaf: &MCLeaf) ->
+    Result<Entry<'a, L>, ()> {
+
+    let mut matrix = leaf.0.clone();
+    for row in &mut matrix {
+        if row.len() > 8 {
+            return Err(());
+        }
+        row.resize(8, State::Dead);
+    }
+    Ok(cells_entry(hl, 3, matrix))
+}
+
+/// The node of size `2^d` with the children given by line numbers in
+/// `indices`, where 0 is a blank block. Nodes with `d` equal to 1 are 2 by 2
+/// squares of cells given by their states instead, as Golly writes them for
+/// rules with more than two states.
+fn build_mc_node<'a, L: LeafSize>(hl: &Hashlife<'a, L>, table: &[(usize,
+    Entry<'a, L>)], d: usize, indices: [[usize; 2]; 2]) ->
+    Result<Entry<'a, L>, ()> {
+
+    if d == 0 || d > MAX_LG_SIZE {
+        return Err(());
+    }
+    if d == 1 {
+        let matrix = try_make_2x2(|i, j| match indices[i][j] {
+            0 => Ok(State::Dead),
+            1 => Ok(State::Alive),
+            _ => Err(()),
+        })?;
+        return Ok(cells_entry(hl, 1, matrix.iter().map(|row| row.to_vec())
+            .collect()));
+    }
+
+    let children = try_make_2x2(|i, j| match indices[i][j] {
+        0 => Ok(blank_entry(hl, d - 1)),
+        index => match table.get(index - 1) {
+            Some(&(lg_size, ref entry)) if lg_size == d - 1 =>
+                Ok(entry.clone()),
+            _ => Err(()),
+        },
+    })?;
+
+    if d > L::LG_LEAF_SIZE {
+        Ok(Entry::Block(hl.node_block(make_2x2(|i, j| match children[i][j] {
+            Entry::Block(block) => block,
+            Entry::Cells(_) => unreachable!("Entries of a size are all blocks"),
+        }))))
+    } else {
+        let mut matrix = Vec::new();
+        for row in &children {
+            match *row {
+                [Entry::Cells(ref west), Entry::Cells(ref east)] => {
+                    for (w, e) in west.iter().zip(east) {
+                        matrix.push([&w[..], &e[..]].concat());
                     }
-                })?;
-                hl.node_block(elems)
+                }
+                _ => unreachable!("Entries of a size are all cells"),
             }
-        };
-        debug_assert!(new_block.lg_size_verified().is_ok());
-        table.push(new_block);
+        }
+        Ok(cells_entry(hl, d, matrix))
+    }
+}
+
+/// The entry of a square of cells of size `2^lg_size`.
+fn cells_entry<'a, L: LeafSize>(hl: &Hashlife<'a, L>, lg_size: usize, matrix:
+    Vec<Vec<State>>) -> Entry<'a, L> {
+
+    if lg_size >= L::LG_LEAF_SIZE {
+        let depth = (lg_size - L::LG_LEAF_SIZE) as u32;
+        Entry::Block(block_from_matrix(hl, depth, matrix.iter()
+            .map(|row| &row[..]).collect()))
+    } else {
+        Entry::Cells(matrix)
     }
-    assert_eq!(table.len(), mclines.len());
-    debug!("Table: {:?}", table);
-    //println!("{:?}", table);
-    table.last().cloned().ok_or(())
 }
 
-fn build_mc_leaf<'a>(hl: &Hashlife<'a>, leaf: &MCLeaf) -> Block<'a> {
+fn blank_entry<'a, L: LeafSize>(hl: &Hashlife<'a, L>, lg_size: usize) ->
+    Entry<'a, L> {
 
-    let full_leaf = leaf.0.iter().map(|row| {
-        let mut new_row = row.clone();
-        new_row.resize(8, State::Dead);
-        new_row
-    }).collect::<Vec<_>>();
-    let matrix = full_leaf.iter().map(|row| &**row).collect();
-    assert!(LG_LEAF_SIZE <= 3);
-    block_from_matrix(hl, 3 - LG_LEAF_SIZE as u32, matrix)
+    if lg_size >= L::LG_LEAF_SIZE {
+        Entry::Block(hl.blank(lg_size))
+    } else {
+        Entry::Cells(vec![vec![State::Dead; 1 << lg_size]; 1 << lg_size])
+    }
 }
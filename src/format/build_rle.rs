@@ -1,9 +1,9 @@
-//! Make a block out of parsed RLE code.
+//! Make a block out of parsed RLE code or plaintext rows.
 
 use std::ops::Range;
 
 use crate::{Block, Hashlife};
-use crate::leaf::{Leaf, LEAF_SIZE, LEAF_Y_SHIFT, LEAF_X_SHIFT};
+use crate::leaf::{Leaf, LeafSize, LEAF_Y_SHIFT, LEAF_X_SHIFT};
 use crate::util::make_2x2;
 use super::parse::{RLE, RLEEncode, RLEToken, State};
 
@@ -36,16 +36,23 @@ fn tokens_to_matrix(tokens: &[RLEToken]) -> Result<Vec<Vec<State>>, ()> {
     Err(())
 }
 
-pub fn block_from_rle<'a>(hl: &Hashlife<'a>, rle: &RLE) -> Result<Block<'a>,
-    ()> {
+pub fn block_from_rle<'a, L: LeafSize>(hl: &Hashlife<'a, L>, rle: &RLE) ->
+    Result<Block<'a, L>, ()> {
+
+    Ok(block_from_rows(hl, tokens_to_matrix(&expand_rle(rle))?))
+}
+
+/// Build a block with the rows of `matrix` from the top, padding each row
+/// and the matrix itself with dead cells to the size of the block.
+pub fn block_from_rows<'a, L: LeafSize>(hl: &Hashlife<'a, L>,
+    mut matrix: Vec<Vec<State>>) -> Block<'a, L> {
 
     use std::cmp::max;
 
-    let mut matrix = tokens_to_matrix(&expand_rle(rle))?;
     let max_row_len = matrix.iter().map(|row| row.len()).max().unwrap_or(0);
     let max_side = max(max_row_len, matrix.len());
-    let res_side: usize = max(max_side, LEAF_SIZE).next_power_of_two();
-    let res_depth = (res_side / LEAF_SIZE).trailing_zeros();
+    let res_side: usize = max(max_side, L::LEAF_SIZE).next_power_of_two();
+    let res_depth = (res_side / L::LEAF_SIZE).trailing_zeros();
 
     for row in &mut matrix {
         row.resize(res_side, State::Dead);
@@ -55,20 +62,20 @@ pub fn block_from_rle<'a>(hl: &Hashlife<'a>, rle: &RLE) -> Result<Block<'a>,
     matrix.resize(res_side, empty_row);
 
     let matrix = matrix.iter().map(|row| &**row).collect();
-    Ok(block_from_matrix(hl, res_depth, matrix))
+    block_from_matrix(hl, res_depth, matrix)
 }
 
-pub fn block_from_matrix<'a>(hl: &Hashlife<'a>, depth: u32, matrix:
-    Vec<&[State]>) -> Block<'a> {
+pub fn block_from_matrix<'a, L: LeafSize>(hl: &Hashlife<'a, L>, depth: u32,
+    matrix: Vec<&[State]>) -> Block<'a, L> {
 
-    assert_eq!(matrix.len(), LEAF_SIZE << depth);
-    for row in &matrix {assert_eq!(row.len(), LEAF_SIZE << depth);}
+    assert_eq!(matrix.len(), L::LEAF_SIZE << depth);
+    for row in &matrix {assert_eq!(row.len(), L::LEAF_SIZE << depth);}
 
     if depth == 0 {
-        hl.leaf(states_to_leaf(&matrix))
+        hl.leaf(states_to_leaf::<L>(&matrix))
     } else {
         // Side-length of subblock.
-        let slen = LEAF_SIZE << (depth - 1);
+        let slen = L::LEAF_SIZE << (depth - 1);
         let subblocks = make_2x2(|i, j| {
             let submatrix = submatrix(&matrix,
                                       i*slen..(i+1)*slen,
@@ -85,7 +92,7 @@ fn submatrix<'a, T>(matrix: &[&'a [T]], outer: Range<usize>, inner:
     matrix[outer].iter().map(|row| &row[inner.clone()]).collect()
 }
 
-fn states_to_leaf(states: &[&[State]]) -> Leaf {
+fn states_to_leaf<L: LeafSize>(states: &[&[State]]) -> Leaf {
     fn state_to_bit(state: State) -> u8 {
         match state {
             State::Dead => 0,
@@ -93,8 +100,8 @@ fn states_to_leaf(states: &[&[State]]) -> Leaf {
         }
     }
 
-    assert!(states.len() == LEAF_SIZE && states.iter().all(|row| row.len() ==
-        LEAF_SIZE));
+    assert!(states.len() == L::LEAF_SIZE && states.iter().all(|row| row.len()
+        == L::LEAF_SIZE));
 
 /*
     state_to_bit(states[0][0])
@@ -103,9 +110,9 @@ fn states_to_leaf(states: &[&[State]]) -> Leaf {
     | state_to_bit(states[1][1]) << 5
 */
     let mut res: Leaf = 0;
-    for y in 0..LEAF_SIZE {
-        for x in 0..LEAF_SIZE {
-            res |= (state_to_bit(states[y][x]) as Leaf) << (y * LEAF_Y_SHIFT
+    for (y, row) in states.iter().enumerate() {
+        for (x, &state) in row.iter().enumerate() {
+            res |= (state_to_bit(state) as Leaf) << (y * LEAF_Y_SHIFT
                 + x * LEAF_X_SHIFT);
         }
     }
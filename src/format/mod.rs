@@ -2,14 +2,47 @@ pub mod parse;
 mod build_rle;
 mod build_mc;
 pub mod write;
+pub mod apgcode;
 
-use crate::{Block, Hashlife};
+use std::cmp::max;
+
+use num::BigInt;
+
+use crate::{Block, Hashlife, LeafSize};
+use crate::global::Pattern;
 use crate::block::Block as RawBlock;
+use crate::util::log2_upper_bigu;
+use self::parse::PatternMetadata;
+
+impl<'a, L: LeafSize> Hashlife<'a, L> {
+    /// Parse a pattern file into a `Pattern`. The top-left cell of the
+    /// pattern is placed at the position given by a `#P`, `#R` or `#CXRLE
+    /// Pos=` line if there is one, and at the origin otherwise. The
+    /// generation count is taken from a `#CXRLE Gen=` line, or from a `#G`
+    /// line of a macrocell file. Life 1.05 and 1.06 files give the absolute
+    /// position of each cell, which is kept. Returns `None` if the file
+    /// can't be parsed.
+    pub fn pattern_from_bytes(&self, bytes: &[u8]) -> Option<Pattern<'a, L>> {
+        self.block_from_bytes(bytes).ok().map(|(block, metadata)| {
+            let mut pattern = match metadata.offset {
+                Some((x, y)) => Pattern::with_origin(block, x, y),
+                None => Pattern::new(block),
+            };
+            if let Some(generation) = metadata.generation {
+                pattern.set_generation(generation);
+            }
+            pattern
+        })
+    }
+
+    /// Parse a pattern file in any of the supported formats into a block,
+    /// together with the information given in its comment lines and header,
+    /// such as its name, author and rule.
+    pub fn block_from_bytes(&self, bytes: &[u8]) -> Result<(Block<'a, L>,
+        PatternMetadata), ()> {
 
-impl<'a> Hashlife<'a> {
-    pub fn block_from_bytes(&self, bytes: &[u8]) -> Result<Block<'a>, ()> {
         use self::parse::{parse_file, ParseOut};
-        use self::build_rle::block_from_rle;
+        use self::build_rle::{block_from_rle, block_from_rows};
         use self::build_mc::build_mc;
         use nom::IResult;
 
@@ -20,8 +53,14 @@ impl<'a> Hashlife<'a> {
         if let IResult::Done(b"", parse_out) =
                 parse_file(&with_newline) {
             match parse_out {
-                ParseOut::RLE(tokens) => block_from_rle(self, &tokens),
-                ParseOut::MC(lines) => build_mc(self, &lines),
+                ParseOut::RLE(tokens, metadata) =>
+                    block_from_rle(self, &tokens).map(|b| (b, metadata)),
+                ParseOut::MC(lines, metadata) =>
+                    build_mc(self, &lines).map(|b| (b, metadata)),
+                ParseOut::Cells(rows, metadata) =>
+                    Ok((block_from_rows(self, rows), metadata)),
+                ParseOut::CellList(cells, metadata) =>
+                    Ok(self.block_from_cells(cells, metadata)),
                 ParseOut::Fail => Err(()),
             }
         } else {
@@ -29,23 +68,43 @@ impl<'a> Hashlife<'a> {
         }
     }
 
-    pub fn raw_block_from_bytes(&self, bytes: &[u8]) -> Result<RawBlock<'a>, ()>
-    {
-        self.block_from_bytes(bytes).map(|b| b.to_raw())
+    /// The smallest block with the north-west corner of the bounding box of
+    /// `cells` at its north-west corner, with the position of that corner
+    /// recorded in `metadata`.
+    fn block_from_cells(&self, cells: Vec<(BigInt, BigInt)>, mut metadata:
+        PatternMetadata) -> (Block<'a, L>, PatternMetadata) {
+
+        let pattern = Pattern::from_cells(*self, cells);
+        match pattern.bounding_box() {
+            Some(bbox) => {
+                let side = max(&bbox.width, &bbox.height);
+                let lg_size = max(log2_upper_bigu(side) as usize,
+                    L::LG_LEAF_SIZE);
+                let block = pattern.block_at(&bbox.x, &bbox.y, lg_size);
+                metadata.offset = Some((bbox.x, bbox.y));
+                (block, metadata)
+            }
+            None => (self.blank(L::LG_LEAF_SIZE), metadata),
+        }
+    }
+
+    pub fn raw_block_from_bytes(&self, bytes: &[u8]) -> Result<RawBlock<'a, L>,
+        ()> {
+        self.block_from_bytes(bytes).map(|(block, _)| block.to_raw())
     }
 
     /// Simpler API for generating a block from a string; useful for quickly
     /// generating a specific block, as in testing.
-    pub fn raw_rle(&self, pat: &'static str) -> RawBlock<'a> {
+    pub fn raw_rle(&self, pat: &'static str) -> RawBlock<'a, L> {
         self.raw_block_from_bytes(pat.as_bytes()).expect("Error parsing static \
             RLE")
     }
 
     /// Simpler API for generating a block from a string; useful for quickly
     /// generating a specific block, as in testing.
-    pub fn rle(&self, pat: &'static str) -> Block<'a> {
+    pub fn rle(&self, pat: &'static str) -> Block<'a, L> {
         self.block_from_bytes(pat.as_bytes()).expect("Error parsing static \
-            RLE")
+            RLE").0
     }
 }
 
@@ -76,9 +135,62 @@ fn test_block_from_bytes() {
             b"x=16,y=16,rule=B3/S23\nbo7bo$2bo7bo$3o5b3o6$9bo$10bo$8b3o!"));
         assert_eq!(hl.raw_block_from_bytes(b"[M2]\n*$$$$$$$$\n4 1 1 1 2"),
                    Err(()));
+        // Golly's format for rules with more than two states, with nodes of
+        // 2 by 2 cells
+        assert_eq!(hl.raw_block_from_bytes(
+            b"[M2]\n#R B3/S23\n1 0 1 0 0\n1 0 0 1 0\n1 1 1 1 0\n1 1 0 0 0\n\
+            2 1 2 3 4"), hl.raw_block_from_bytes(b"bo$2bo$3o$o!"));
+        assert_eq!(hl.raw_block_from_bytes(
+            b"[M2]\n1 0 1 0 0\n1 0 0 1 0\n1 1 1 0 0\n1 1 0 0 0\n2 1 2 3 4\n\
+            3 5 0 0 0"), hl.raw_block_from_bytes(b"bo$2bo$3o5$8b!"));
+        assert_eq!(hl.raw_block_from_bytes(b"[M2]\n1 0 1 2 0"), Err(()));
+        assert_eq!(hl.raw_block_from_bytes(b"[M2]\n5 0 0 0 0"),
+            Ok(hl.blank(5).to_raw()));
+        assert_eq!(hl.raw_block_from_bytes(b"[M2]\n4096 0 0 0 0"),
+            Ok(hl.blank(4096).to_raw()));
+        // Malformed files
+        for &bad in &[&b"[M2]\n.........*$$$$$$$$"[..], b"[M2]\n4 1 0 0 0",
+            b"[M2]\n$$$$$$$$\n5 1 0 0 0", b"[M2]\n0 0 0 0 0",
+            b"[M2]\n1 0 1 0 0\n3 1 1 1 1", b"[M2]\n#R B3/S23\n",
+            b"[M2]\n4097 0 0 0 0", b"[M2]\n1000000000000 0 0 0 0",
+            b"[M2]\n99999999999999999999 0 0 0 0"] {
+            assert_eq!(hl.raw_block_from_bytes(bad), Err(()));
+        }
+
+        // .cells
+        assert_eq!(
+            hl.raw_block_from_bytes(b"!Name: Glider\n!\n.O\n..O\nOOO\n"),
+            hl.raw_block_from_bytes(b"bo$2bo$3o!"));
+        assert_eq!(hl.raw_block_from_bytes(b"!Name: Beacon\nOO\nO\n\n..OO"),
+            hl.raw_block_from_bytes(b"2o$o$$2b2o!"));
+        assert_eq!(hl.raw_block_from_bytes(b".O\nO*"), Err(()));
+        assert_eq!(hl.raw_block_from_bytes(b".O\n..*"), Err(()));
+
+        // Life 1.05 and 1.06
+        assert_eq!(hl.raw_block_from_bytes(b"#Life 1.06\n5 -2\n6 -2\n5 -1"),
+            hl.raw_block_from_bytes(b"2o$o!"));
+        assert_eq!(hl.raw_block_from_bytes(b"#Life 1.06\n1 2 3"), Err(()));
+        assert_eq!(hl.raw_block_from_bytes(b"#Life 1.05\n#P 0 0\n**\n*"),
+            hl.raw_block_from_bytes(b"2o$o!"));
     });
 }
 
+#[cfg(test)]
+fn mc_cell_nodes<L: LeafSize>() {
+    Hashlife::<L>::with_new_leaf(|hl| {
+        assert_eq!(hl.raw_block_from_bytes(b"[M2]\n1 0 1 0 0\n1 0 0 1 0\n\
+            1 1 1 0 0\n1 1 0 0 0\n2 1 2 3 4\n3 5 0 5 0"),
+            hl.raw_block_from_bytes(b"bo$2bo$3o2$bo$2bo$3o!"));
+        assert_eq!(hl.raw_block_from_bytes(b"[M2]\n1 1 0 0 1"),
+            hl.raw_block_from_bytes(b"o$bo!"));
+        assert_eq!(hl.raw_block_from_bytes(b"[M2]\n1 1 0 0 1\n3 1 0 0 0"),
+            Err(()));
+    });
+}
+
+#[cfg(test)]
+leaf_size_test!(test_mc_cell_nodes, mc_cell_nodes);
+
 // From failure in write::test::test_round_trip
 #[test]
 fn test_empty_rle() {
@@ -86,3 +198,69 @@ fn test_empty_rle() {
         hl.raw_block_from_bytes(b"!\n").unwrap();
     });
 }
+
+#[test]
+fn test_pattern_from_bytes() {
+    use num::{BigInt, BigUint};
+
+    Hashlife::with_new(|hl| {
+        let pattern = hl.pattern_from_bytes(b"#P 5 -3\nbo$2bo$3o!").unwrap();
+        let glider = hl.rle("bo$2bo$3o!");
+        assert_eq!(pattern, Pattern::with_origin(glider, BigInt::from(5),
+            BigInt::from(-3)));
+        assert!(pattern != Pattern::new(glider));
+        assert_eq!(hl.pattern_from_bytes(b"bo$2bo$3o!"),
+            Some(Pattern::new(glider)));
+
+        let pattern = hl.pattern_from_bytes(
+            b"#CXRLE Pos=5,-3 Gen=12\nbo$2bo$3o!").unwrap();
+        assert_eq!(pattern, Pattern::with_origin(glider, BigInt::from(5),
+            BigInt::from(-3)));
+        assert_eq!(*pattern.generation(), BigUint::from(12u8));
+
+        let pattern = hl.pattern_from_bytes(
+            b"[M2] (golly 4.2)\n#R B3/S23\n#G 1000\n.*$..*$***$$$$$$").unwrap();
+        assert_eq!(pattern, Pattern::new(glider));
+        assert_eq!(*pattern.generation(), BigUint::from(1000u16));
+        let (_, metadata) = hl.block_from_bytes(
+            b"[M2]\n#R B36/S23\n#C First\n#C Second\n.*$..*$***$$$$$$").unwrap();
+        assert_eq!(metadata.rule, Some("B36/S23".to_string()));
+        assert_eq!(metadata.comments, vec!["First".to_string(),
+            "Second".to_string()]);
+        let (_, metadata) = hl.block_from_bytes(
+            b"#N Glider\n#O Richard K. Guy\nx = 3, y = 3\nbo$2bo$3o!").unwrap();
+        assert_eq!(metadata.name, Some("Glider".to_string()));
+        assert_eq!(metadata.author, Some("Richard K. Guy".to_string()));
+        assert_eq!(metadata.rule, None);
+        let (_, metadata) = hl.block_from_bytes(
+            b"!Name: Glider\n!Author: Richard K. Guy\n.O\n..O\nOOO").unwrap();
+        assert_eq!(metadata.name, Some("Glider".to_string()));
+        assert_eq!(metadata.author, Some("Richard K. Guy".to_string()));
+
+        // Life 1.06 keeps the coordinates of far apart cells.
+        let far = "1000000000000000000000";
+        let bytes = format!("#Life 1.06\n0 -1\n1 0\n-1 1\n0 1\n1 1\n\
+            {} -{}\n", far, far);
+        let pattern = hl.pattern_from_bytes(bytes.as_bytes()).unwrap();
+        let far: BigInt = far.parse().unwrap();
+        assert_eq!(pattern.population(), BigUint::from(6u8));
+        assert!(pattern.get_cell(&far, &-&far));
+        assert!(pattern.get_cell(&BigInt::from(-1), &BigInt::from(1)));
+        assert_eq!(pattern.extract(&glider_box(-1, -1)),
+            Pattern::with_origin(glider, BigInt::from(-1), BigInt::from(-1)));
+
+        let pattern = hl.pattern_from_bytes(
+            b"#Life 1.05\n#P 4 7\n.*\n..*\n***").unwrap();
+        assert_eq!(pattern, Pattern::with_origin(glider, BigInt::from(4),
+            BigInt::from(7)));
+        assert_eq!(hl.raw_block_from_bytes(
+            b"#Life 1.05\n#P 4 7\n.*\n..*\n***"), Ok(glider.to_raw()));
+        assert_eq!(hl.raw_block_from_bytes(b"#Life 1.06\n"),
+            Ok(hl.raw_blank(1)));
+    });
+
+    fn glider_box(x: i32, y: i32) -> crate::global::Rect {
+        crate::global::Rect {x: x.into(), y: y.into(), width: 3u8.into(),
+            height: 3u8.into()}
+    }
+}
@@ -1,6 +1,8 @@
+use std::convert::TryFrom;
 use std::str::{self, FromStr};
 
 use nom::*;
+use num::{BigInt, BigUint};
 
 // CFG for avoid clippy warning
 #[cfg(test)]
@@ -13,17 +15,54 @@ macro_rules! assert_parse {
     }
 }
 
-named!(pub parse_file<&[u8], ParseOut>,
-    map!(many0!(parse_line), process_lines)
-);
+/// Parses the lines of a file like `many0!(parse_line)`, except that lines
+/// after a `[M2]` header are tried as macrocell lines first, since a leaf
+/// such as `$$**$$$$$$` starts like an RLE line.
+pub fn parse_file(input: &[u8]) -> IResult<&[u8], ParseOut> {
+    let mut lines = Vec::new();
+    let mut rest = input;
+    let mut macrocell = false;
+    while !rest.is_empty() {
+        let parsed = if macrocell {
+            mc_file_line(rest)
+        } else {
+            parse_line(rest)
+        };
+        match parsed {
+            IResult::Done(next, line) => {
+                if let LineParse::MCHeader(_) = line {
+                    macrocell = true;
+                }
+                lines.push(line);
+                rest = next;
+            }
+            IResult::Error(_) => break,
+            IResult::Incomplete(_) =>
+                return IResult::Incomplete(Needed::Unknown),
+        }
+    }
+    IResult::Done(rest, process_lines(lines))
+}
 
 named!(parse_line<&[u8], LineParse>,
     chain!(
         out: alt!(
-              map!(mc_header, LineParse::MCHeader)
+              map!(cells_comment, LineParse::CellsComment)
+            | map!(mc_header, LineParse::MCHeader)
+            | map!(life105_header, |_| LineParse::Life105Header)
+            | map!(life106_header, |_| LineParse::Life106Header)
             | map!(rle_meta, LineParse::RLEMeta)
             | map!(rle_line, LineParse::RLELine)
             | map!(mc_line, LineParse::MCLine)
+            | cells_line
+            | map!(life_cell, LineParse::LifeCell)
+            | map!(rle_offset, LineParse::Offset)
+            | map!(cxrle, LineParse::ExtendedRLE)
+            | map!(comment_text, LineParse::CommentText)
+            | map!(name_line, LineParse::Name)
+            | map!(author_line, LineParse::Author)
+            | map!(rule_line, LineParse::Rule)
+            | map!(generation_line, LineParse::Generation)
             | map!(comment, LineParse::Comment)
         )
         ~ line_ending,
@@ -31,22 +70,64 @@ named!(parse_line<&[u8], LineParse>,
     )
 );
 
+named!(mc_file_line<&[u8], LineParse>,
+    alt!(
+        chain!(line: mc_line ~ line_ending, || LineParse::MCLine(line))
+        | parse_line
+    )
+);
+
 // Unstable type before I figure out the output of the parser
 #[derive(Debug, PartialEq)]
 pub enum ParseOut {
-    RLE(RLEBuf),
-    MC(Vec<MCLine>),
+    RLE(RLEBuf, PatternMetadata),
+    MC(Vec<MCLine>, PatternMetadata),
+    Cells(Vec<Vec<State>>, PatternMetadata),
+    /// Live cells at absolute (x, y) coordinates, from Life 1.05 or 1.06
+    CellList(Vec<(BigInt, BigInt)>, PatternMetadata),
     Fail,
 }
 pub type RLEOut = RLEBuf;
 
+/// Information about a pattern given in the header and comment lines of its
+/// file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PatternMetadata {
+    /// Name of the pattern, from a `#N` line or a plaintext `!Name:` line
+    pub name: Option<String>,
+    /// Author of the pattern, from a `#O` line or a plaintext `!Author:` line
+    pub author: Option<String>,
+    /// Text of the `#C` and `#c` comment lines, or of the other comment lines
+    /// of a plaintext file
+    pub comments: Vec<String>,
+    /// Position of the top-left cell, from a `#P`, `#R` or `#CXRLE Pos=` line
+    pub offset: Option<(BigInt, BigInt)>,
+    /// Rule, from the RLE header, a `#r` line or a macrocell `#R` line
+    pub rule: Option<String>,
+    /// Generation count, from a `#CXRLE Gen=` line or a macrocell `#G` line
+    pub generation: Option<BigUint>,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 enum LineParse {
     Comment(Comment),
+    CommentText(String),
+    Name(String),
+    Author(String),
+    Offset((BigInt, BigInt)),
+    Rule(String),
+    Generation(BigUint),
+    ExtendedRLE(PatternMetadata),
     RLEMeta(RLEMeta),
     RLELine(RLEBuf),
     MCHeader(MCHeader),
     MCLine(MCLine),
+    CellsComment(String),
+    CellsLine(Vec<State>),
+    Life105Header,
+    Life105Line(Vec<State>),
+    Life106Header,
+    LifeCell((BigInt, BigInt)),
 }
 
 fn process_lines(lines: Vec<LineParse>) -> ParseOut {
@@ -57,14 +138,71 @@ fn process_lines(lines: Vec<LineParse>) -> ParseOut {
         Start,
         RLE(Option<RLEMeta>, RLEBuf),
         MC(MCHeader, Vec<MCLine>),
+        Cells(Vec<Vec<State>>),
+        // The live cells so far, the position of the next row and whether a
+        // row of the current block has been read
+        Life105(Vec<(BigInt, BigInt)>, (BigInt, BigInt), bool),
+        Life106(Vec<(BigInt, BigInt)>),
     }
     
     let mut parse_state = PS::Start;
+    let mut metadata = PatternMetadata::default();
+    // Blank lines before the first line which tells the format apart, which
+    // are rows if it turns out to be plaintext
+    let mut leading_blanks = 0;
 
     for line in lines {
         parse_state = match (parse_state, line) {
+            // In plaintext, blank lines are rows of dead cells.
+            (PS::Cells(mut rows), LP::Comment(_)) => {
+                rows.push(Vec::new());
+                PS::Cells(rows)
+            }
+            (PS::Start, LP::Comment(_)) => {
+                leading_blanks += 1;
+                PS::Start
+            }
+            // So are the blank lines within a block of Life 1.05 rows.
+            (PS::Life105(cells, (x, y), true), LP::Comment(_)) => {
+                PS::Life105(cells, (x, y + 1), true)
+            }
             (ps, LP::Comment(_)) => {ps},
+            (ps, LP::CommentText(text)) => {
+                metadata.comments.push(text);
+                ps
+            }
+            (ps, LP::Name(name)) => {
+                metadata.name = Some(name);
+                ps
+            }
+            (ps, LP::Author(author)) => {
+                metadata.author = Some(author);
+                ps
+            }
+            (ps, LP::Rule(rule)) => {
+                metadata.rule = Some(rule);
+                ps
+            }
+            (ps, LP::Generation(generation)) => {
+                metadata.generation = Some(generation);
+                ps
+            }
+            (PS::Life105(cells, _, _), LP::Offset(pos)) => {
+                PS::Life105(cells, pos, false)
+            }
+            (ps @ PS::Start, LP::Offset(pos)) | (ps @ PS::RLE(_, _),
+                LP::Offset(pos)) => {
+                metadata.offset = Some(pos);
+                ps
+            }
+            (ps @ PS::Start, LP::ExtendedRLE(cxrle)) | (ps @ PS::RLE(_, _),
+                LP::ExtendedRLE(cxrle)) => {
+                metadata.offset = cxrle.offset.or(metadata.offset);
+                metadata.generation = cxrle.generation.or(metadata.generation);
+                ps
+            }
             (PS::Start, LP::RLEMeta(meta)) => {
+                metadata.rule = meta.rule.clone().or(metadata.rule);
                 PS::RLE(Some(meta), Vec::new())
             }
             (PS::Start, LP::RLELine(tokens)) => {    
@@ -81,6 +219,47 @@ fn process_lines(lines: Vec<LineParse>) -> ParseOut {
                 lines.push(line);
                 PS::MC(h, lines)
             }
+            (PS::Start, LP::CellsComment(text)) => {
+                add_cells_comment(&mut metadata, text);
+                PS::Cells(vec![Vec::new(); leading_blanks])
+            }
+            (PS::Start, LP::CellsLine(row)) => {
+                let mut rows = vec![Vec::new(); leading_blanks];
+                rows.push(row);
+                PS::Cells(rows)
+            }
+            (ps @ PS::Cells(_), LP::CellsComment(text)) => {
+                add_cells_comment(&mut metadata, text);
+                ps
+            }
+            // An empty comment line, which `parse_line` can't tell apart
+            // from the end of an RLE pattern
+            (ps @ PS::Cells(_), LP::RLELine(tokens))
+                if tokens == [(1, RLEToken::EndBlock)] => {ps},
+            (PS::Cells(mut rows), LP::CellsLine(row)) => {
+                rows.push(row);
+                PS::Cells(rows)
+            }
+            (PS::Start, LP::Life105Header) => {
+                PS::Life105(Vec::new(), (BigInt::from(0), BigInt::from(0)),
+                    false)
+            }
+            (PS::Life105(mut cells, (x, y), _), LP::Life105Line(row))
+                | (PS::Life105(mut cells, (x, y), _), LP::CellsLine(row)) => {
+                for (i, &state) in row.iter().enumerate() {
+                    if state == State::Alive {
+                        cells.push((&x + i, y.clone()));
+                    }
+                }
+                PS::Life105(cells, (x, y + 1), true)
+            }
+            (PS::Start, LP::Life106Header) => {
+                PS::Life106(Vec::new())
+            }
+            (PS::Life106(mut cells), LP::LifeCell(cell)) => {
+                cells.push(cell);
+                PS::Life106(cells)
+            }
             _ => {
                 // Inappropiate line
                 return ParseOut::Fail;
@@ -89,12 +268,33 @@ fn process_lines(lines: Vec<LineParse>) -> ParseOut {
     }
 
     match parse_state {
-        PS::RLE(_, tokens) => ParseOut::RLE(tokens),
-        PS::MC(_, lines) => ParseOut::MC(lines),
+        PS::RLE(_, tokens) => ParseOut::RLE(tokens, metadata),
+        PS::MC(_, lines) => ParseOut::MC(lines, metadata),
+        PS::Cells(mut rows) => {
+            // Drop the blank lines at the end of the file.
+            while rows.last().map(Vec::len) == Some(0) {
+                rows.pop();
+            }
+            ParseOut::Cells(rows, metadata)
+        }
+        PS::Life105(cells, _, _) | PS::Life106(cells) =>
+            ParseOut::CellList(cells, metadata),
         _ => ParseOut::Fail,
     }
 }
 
+/// Record the text of a plaintext comment line, after the `!`.
+fn add_cells_comment(metadata: &mut PatternMetadata, text: String) {
+    let text = text.trim();
+    if let Some(name) = text.strip_prefix("Name:") {
+        metadata.name = Some(name.trim_start().to_string());
+    } else if let Some(author) = text.strip_prefix("Author:") {
+        metadata.author = Some(author.trim_start().to_string());
+    } else {
+        metadata.comments.push(text.to_string());
+    }
+}
+
 named!(uint<&[u8], u64>,
     map_res!(
         digit,
@@ -104,15 +304,197 @@ named!(uint<&[u8], u64>,
     )
 );
 
+named!(big_uint<&[u8], BigUint>,
+    map_res!(
+        digit,
+        // `unwrap` should never panic since `digit` only accepts ASCII
+        // characters.
+        |x| BigUint::from_str(str::from_utf8(x).unwrap())
+    )
+);
+
+named!(signed_int<&[u8], BigInt>,
+    map_res!(
+        recognize!(tuple!(opt!(tag!("-")), digit)),
+        // `unwrap` should never panic since only ASCII characters are
+        // accepted.
+        |x| BigInt::from_str(str::from_utf8(x).unwrap())
+    )
+);
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct Comment;
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum CXRLEField {
+    Pos(BigInt, BigInt),
+    Gen(BigUint),
+    Other,
+}
+
+// Golly's extended RLE line, as in `#CXRLE Pos=-3,4 Gen=100`. Unknown fields
+// are ignored.
+named!(cxrle<&[u8], PatternMetadata>,
+    chain!(
+        space? ~
+        tag!("#CXRLE") ~
+        fields: many0!(chain!(space ~ field: cxrle_field, || field)) ~
+        space?,
+        || {
+            let mut info = PatternMetadata::default();
+            for field in fields {
+                match field {
+                    CXRLEField::Pos(x, y) => info.offset = Some((x, y)),
+                    CXRLEField::Gen(gen) => info.generation = Some(gen),
+                    CXRLEField::Other => {},
+                }
+            }
+            info
+        }
+    )
+);
+
+named!(cxrle_field<&[u8], CXRLEField>,
+    alt!(
+          chain!(
+            tag!("Pos=") ~
+            x: signed_int ~
+            tag!(",") ~
+            y: signed_int,
+            || CXRLEField::Pos(x, y)
+          )
+        | chain!(tag!("Gen=") ~ gen: big_uint, || CXRLEField::Gen(gen))
+        | map!(is_not!(" \t\r\n"), |_| CXRLEField::Other)
+    )
+);
+
+// Position of the top-left cell of an RLE pattern, as in `#P x y`. `#R` is
+// treated as a synonym.
+named!(rle_offset<&[u8], (BigInt, BigInt)>,
+    chain!(
+        space? ~
+        tag!("#") ~
+        alt!(tag!("P") | tag!("R")) ~
+        space ~
+        x: signed_int ~
+        space ~
+        y: signed_int ~
+        space?,
+        || (x, y)
+    )
+);
+
+// Comment line, as in `#C Found in 1970`
+named!(comment_text<&[u8], String>,
+    chain!(
+        space? ~
+        alt!(tag!("#C") | tag!("#c")) ~
+        space? ~
+        text: not_line_ending,
+        || String::from_utf8_lossy(text).into_owned()
+    )
+);
+
+// Name of the pattern, as in `#N Glider`
+named!(name_line<&[u8], String>,
+    chain!(
+        space? ~
+        tag!("#N") ~
+        space ~
+        text: not_line_ending,
+        || String::from_utf8_lossy(text).trim_end().to_string()
+    )
+);
+
+// Author of the pattern, as in `#O John Conway`
+named!(author_line<&[u8], String>,
+    chain!(
+        space? ~
+        tag!("#O") ~
+        space ~
+        text: not_line_ending,
+        || String::from_utf8_lossy(text).trim_end().to_string()
+    )
+);
+
+// Rule, as in `#r B3/S23`, or `#R B3/S23` in a macrocell file. `rle_offset`
+// is tried first since in RLE files `#R x y` gives the position instead.
+named!(rule_line<&[u8], String>,
+    chain!(
+        space? ~
+        alt!(tag!("#R") | tag!("#r")) ~
+        space ~
+        rule: is_not!(" \t\r\n") ~
+        space?,
+        || String::from_utf8_lossy(rule).into_owned()
+    )
+);
+
+// Generation count of a macrocell file, as in `#G 1000`
+named!(generation_line<&[u8], BigUint>,
+    chain!(
+        space? ~
+        tag!("#G") ~
+        space ~
+        generation: big_uint ~
+        space?,
+        || generation
+    )
+);
+
 named!(comment<&[u8], Comment>,
     map!(tuple!(opt!(space), opt!(tuple!(tag!("#"), not_line_ending))),
         |_| Comment
     )
 );
 
+// Comment line of a plaintext (`.cells`) file, as in `!Name: Glider`. A lone
+// `!` is left to `rle_line` since it also ends an RLE pattern.
+named!(cells_comment<&[u8], String>,
+    map!(tuple!(tag!("!"), is_not!("\r\n")),
+        |(_, text)| String::from_utf8_lossy(text).into_owned()
+    )
+);
+
+// Row of a plaintext file, with `.` for dead cells and `O` for live cells,
+// or of a Life 1.05 file, with `*` for live cells. Both kinds are read by
+// one parser, since `alt!` wouldn't go on to another parser after one of
+// them had read the start of a row of the other kind.
+named!(cells_line<&[u8], LineParse>,
+    map_opt!(is_a!(".O*"), cells_row)
+);
+
+fn cells_row(text: &[u8]) -> Option<LineParse> {
+    let row = text.iter()
+        .map(|&c| if c == b'.' {State::Dead} else {State::Alive})
+        .collect();
+    match (text.contains(&b'O'), text.contains(&b'*')) {
+        (_, false) => Some(LineParse::CellsLine(row)),
+        (false, true) => Some(LineParse::Life105Line(row)),
+        (true, true) => None,
+    }
+}
+
+named!(life105_header<&[u8], ()>,
+    chain!(tag!("#Life") ~ space ~ tag!("1.05") ~ space?, || ())
+);
+
+named!(life106_header<&[u8], ()>,
+    chain!(tag!("#Life") ~ space ~ tag!("1.06") ~ space?, || ())
+);
+
+// Live cell of a Life 1.06 file, as in `-3 12`
+named!(life_cell<&[u8], (BigInt, BigInt)>,
+    chain!(
+        space? ~
+        x: signed_int ~
+        space ~
+        y: signed_int ~
+        space?,
+        || (x, y)
+    )
+);
+
 pub type RLEBuf = RLEEncodeBuf<RLEToken>;
 pub type RLE = RLEEncode<RLEToken>;
 pub type RLEEncodeBuf<A> = Vec<(usize, A)>;
@@ -123,8 +505,7 @@ pub type RLEEncode<A> = [(usize, A)];
 struct RLEMeta {
     x: u64,
     y: u64,
-    //TODO
-    //rule: ...
+    rule: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -153,6 +534,16 @@ named!(rle_meta<&[u8], RLEMeta>,
         tag!("=") ~
         space? ~
         y: uint ~
+        rule: rle_rule? ~
+        space?
+        ,
+        || {RLEMeta {x: x, y: y, rule}}
+    )
+);
+
+// The rule at the end of an RLE header, as in `, rule = B3/S23`
+named!(rle_rule<&[u8], String>,
+    chain!(
         space? ~
         tag!(",") ~
         space? ~
@@ -161,9 +552,9 @@ named!(rle_meta<&[u8], RLEMeta>,
         tag!("=") ~
         space? ~
         // Replace with rule grammar
-        not_line_ending
+        rule: not_line_ending
         ,
-        || {RLEMeta {x: x, y: y}}
+        || String::from_utf8_lossy(rule).trim_end().to_string()
     )
 );
 
@@ -232,20 +623,29 @@ named!(mc_leaf<&[u8], MCLeaf>,
     )), MCLeaf)
 );
 
-// FIXME: potential truncation with "as" 
 named!(mc_node<&[u8], MCNode>,
-    chain!(
-        space? ~
-        d: uint ~ space ~
-        b0: uint ~ space ~
-        b1: uint ~ space ~
-        b2: uint ~ space ~
-        b3: uint ~ space?,
-        || MCNode(d as usize, b0 as usize, b1 as usize, b2 as usize, b3 as
-            usize)
+    map_opt!(
+        chain!(
+            space? ~
+            d: uint ~ space ~
+            b0: uint ~ space ~
+            b1: uint ~ space ~
+            b2: uint ~ space ~
+            b3: uint ~ space?,
+            || [d, b0, b1, b2, b3]
+        ),
+        mc_node_fields
     )
 );
 
+/// Fails if a field doesn't fit in a `usize`.
+fn mc_node_fields(fields: [u64; 5]) -> Option<MCNode> {
+    let [d, b0, b1, b2, b3] = fields;
+    let convert = |x| usize::try_from(x).ok();
+    Some(MCNode(convert(d)?, convert(b0)?, convert(b1)?, convert(b2)?,
+        convert(b3)?))
+}
+
 #[test]
 fn test_rle_line() {
     use self::RLEToken::*;
@@ -266,7 +666,7 @@ fn test_rle_line() {
 #[test]
 fn test_parse_rle_meta() {
     assert_parse!(b" x = 3 , y = 8 , rule = ?" => rle_meta,
-        RLEMeta {x: 3, y: 8});
+        RLEMeta {x: 3, y: 8, rule: Some("?".to_string())});
     let res = rle_meta(b"x=3,y=8,rule=B3/23");
     match res {
         IResult::Done(_, _) => {},
@@ -285,9 +685,9 @@ fn test_parse_rle_meta() {
         }
     }
     assert_parse!(b"x=3,y=8,rule=B3/S23" => rle_meta,
-        RLEMeta {x: 3, y: 8});
+        RLEMeta {x: 3, y: 8, rule: Some("B3/S23".to_string())});
     assert_parse!(b"x=33,y=27421,rule=B3/S23" => rle_meta,
-        RLEMeta {x:33, y:27421});
+        RLEMeta {x:33, y:27421, rule: Some("B3/S23".to_string())});
 }
 
 #[test]
@@ -295,7 +695,10 @@ fn test_parse_line() {
     use self::State::*;
 
     assert_parse!(b"x=1,y=1,rule=B3/S23\n" => parse_line,
-        LineParse::RLEMeta(RLEMeta {x: 1, y: 1}));
+        LineParse::RLEMeta(RLEMeta {x: 1, y: 1, rule: Some("B3/S23"
+        .to_string())}));
+    assert_parse!(b"x = 3, y = 2\n" => parse_line, LineParse::RLEMeta(RLEMeta
+        {x: 3, y: 2, rule: None}));
     assert_parse!(b"3bo\n" => parse_line, 
 //        LineParse::RLELine(vec![RLEToken::Run(3, State::Dead), RLEToken::Run(1,
 //                                    State::Alive)]));
@@ -303,6 +706,24 @@ fn test_parse_line() {
             RLEToken::State(State::Alive))])
     );
     assert_parse!(b" #  Comment!\n" => parse_line, LineParse::Comment(Comment));
+    assert_parse!(b"#P -12 7\n" => parse_line, LineParse::Offset(
+        (BigInt::from(-12), BigInt::from(7))));
+    assert_parse!(b"#R 0 -1\n" => parse_line, LineParse::Offset(
+        (BigInt::from(0), BigInt::from(-1))));
+    assert_parse!(b"#Pfoo\n" => parse_line, LineParse::Comment(Comment));
+    assert_parse!(b"#CXRLE Pos=-1,20\n" => parse_line, LineParse::ExtendedRLE(
+        PatternMetadata {offset: Some((BigInt::from(-1), BigInt::from(20))),
+        ..PatternMetadata::default()}));
+    assert_parse!(b"#CXRLE Pos=3,-4 Gen=5\n" => parse_line,
+        LineParse::ExtendedRLE(PatternMetadata {offset: Some((BigInt::from(3),
+        BigInt::from(-4))), generation: Some(BigUint::from(5u8)),
+        ..PatternMetadata::default()}));
+    assert_parse!(b"#CXRLE Foo=bar Gen=12345678901234567890123\n" =>
+        parse_line, LineParse::ExtendedRLE(PatternMetadata {generation:
+        Some("12345678901234567890123".parse().unwrap()),
+        ..PatternMetadata::default()}));
+    assert_parse!(b"#CXRLE\n" => parse_line, LineParse::ExtendedRLE(
+        PatternMetadata::default()));
     assert_parse!(b"[M2]\n" => parse_line, LineParse::MCHeader(MCHeader));
     assert_parse!(b".*$..*$***$$$$$$\n" => parse_line,
         LineParse::MCLine(MCLine::Leaf(MCLeaf(
@@ -310,6 +731,34 @@ fn test_parse_line() {
         Alive], vec![], vec![], vec![], vec![], vec![]]))));
     assert_parse!(b"4 1 1 0 1\n" => parse_line, LineParse::MCLine(MCLine::Node(
         MCNode(4, 1, 1, 0, 1))));
+    assert_parse!(b"#R B3/S23\n" => parse_line, LineParse::Rule(
+        "B3/S23".to_string()));
+    assert_parse!(b"#G 1000\n" => parse_line, LineParse::Generation(
+        BigUint::from(1000u16)));
+    assert_parse!(b"#C Found by  Conway\n" => parse_line,
+        LineParse::CommentText("Found by  Conway".to_string()));
+    assert_parse!(b"#C\n" => parse_line, LineParse::CommentText(String::new()));
+    assert_parse!(b"#c lowercase\n" => parse_line,
+        LineParse::CommentText("lowercase".to_string()));
+    assert_parse!(b"#N Glider \n" => parse_line, LineParse::Name(
+        "Glider".to_string()));
+    assert_parse!(b"#O John Conway\n" => parse_line, LineParse::Author(
+        "John Conway".to_string()));
+    assert_parse!(b"#r B36/S23\n" => parse_line, LineParse::Rule(
+        "B36/S23".to_string()));
+    assert_parse!(b"1 0 1 1 0\n" => parse_line, LineParse::MCLine(MCLine::Node(
+        MCNode(1, 0, 1, 1, 0))));
+    assert_parse!(b"$$**$$$$$$\n" => mc_file_line, LineParse::MCLine(
+        MCLine::Leaf(MCLeaf(vec![vec![], vec![], vec![Alive, Alive], vec![],
+        vec![], vec![], vec![], vec![]]))));
+    assert_parse!(b"!Name: Glider\n" => parse_line,
+        LineParse::CellsComment("Name: Glider".to_string()));
+    assert_parse!(b".O.\n" => parse_line, LineParse::CellsLine(
+        vec![Dead, Alive, Dead]));
+    assert_parse!(b"..*\n" => parse_line, LineParse::Life105Line(
+        vec![Dead, Dead, Alive]));
+    assert_parse!(b"!\n" => parse_line, LineParse::RLELine(
+        vec![(1, RLEToken::EndBlock)]));
 }
 
 #[test]
@@ -322,34 +771,134 @@ fn test_process_lines() {
     let alive = State(Alive);
     let dead = State(Dead);
 
-    let meta = LineParse::RLEMeta(RLEMeta {x: 5, y: 5});
+    let meta = LineParse::RLEMeta(RLEMeta {x: 5, y: 5, rule: Some("B3/S23"
+        .to_string())});
+    let rule = PatternMetadata {rule: Some("B3/S23".to_string()),
+        ..PatternMetadata::default()};
     let line0 = LineParse::RLELine(vec![(1, alive), (1, EndLine), (1, alive)]);
     let line1 = LineParse::RLELine(vec![(3, dead), (1, alive), (1, EndBlock)]);
 
+    let offset = LineParse::Offset((BigInt::from(-3), BigInt::from(4)));
+
     assert_eq!(process_lines(vec![line0.clone()]), RLE(vec![(1, alive), (1,
-        EndLine), (1, alive)]));
+        EndLine), (1, alive)], PatternMetadata::default()));
     assert_eq!(process_lines(vec![meta.clone(), line0.clone()]),
-        RLE(vec![(1, alive), (1, EndLine), (1, alive)]));
+        RLE(vec![(1, alive), (1, EndLine), (1, alive)], rule.clone()));
     assert_eq!(process_lines(vec![line0.clone(), line1.clone()]),
         RLE(vec![(1, alive), (1, EndLine), (1, alive), (3, dead), (1, alive),
-            (1, EndBlock)]));
+            (1, EndBlock)], PatternMetadata::default()));
+    assert_eq!(process_lines(vec![offset.clone(), meta.clone(),
+        line0.clone()]), RLE(vec![(1, alive), (1, EndLine), (1, alive)],
+        PatternMetadata {offset: Some((BigInt::from(-3), BigInt::from(4))),
+        ..rule}));
     assert_eq!(process_lines(vec![line0, meta]), Fail);
 }
 
+#[test]
+fn test_parse_mc() {
+    use self::State::*;
+
+    let leaf = MCLine::Leaf(MCLeaf(vec![vec![Dead, Alive], vec![], vec![],
+        vec![], vec![], vec![], vec![], vec![]]));
+    assert_parse!(b"[M2] (golly 4.2)\n#R B3/S23\n#G 42\n#C A comment\n\
+        #FRAMES 3\n.*$$$$$$$$\n4 1 0 0 1\n" => parse_file, ParseOut::MC(
+        vec![leaf, MCLine::Node(MCNode(4, 1, 0, 0, 1))], PatternMetadata {rule:
+        Some("B3/S23".to_string()), generation: Some(BigUint::from(42u8)),
+        comments: vec!["A comment".to_string()],
+        ..PatternMetadata::default()}));
+    let leaf = MCLine::Leaf(MCLeaf(vec![vec![], vec![], vec![Alive, Alive],
+        vec![], vec![], vec![], vec![], vec![]]));
+    assert_parse!(b"[M2]\n$$**$$$$$$\n" => parse_file, ParseOut::MC(
+        vec![leaf], PatternMetadata::default()));
+}
+
+#[test]
+fn test_parse_cells() {
+    use self::State::*;
+
+    let named = |name: &str| PatternMetadata {name: Some(name.to_string()),
+        ..PatternMetadata::default()};
+
+    assert_parse!(b"!Name: Glider\n!\n.O\n..O\nOOO\n" => parse_file,
+        ParseOut::Cells(vec![vec![Dead, Alive], vec![Dead, Dead, Alive],
+        vec![Alive, Alive, Alive]], named("Glider")));
+    // Blank lines are empty rows, except at the end.
+    assert_parse!(b"!Name: Blinkers\n\nOOO\n\n\nOOO\n\n" => parse_file,
+        ParseOut::Cells(vec![vec![], vec![Alive, Alive, Alive], vec![],
+        vec![], vec![Alive, Alive, Alive]], named("Blinkers")));
+    assert_parse!(b"!Name: Empty\n" => parse_file, ParseOut::Cells(vec![],
+        named("Empty")));
+    // Including the ones before the first row
+    assert_parse!(b"\nOO\nOO\n" => parse_file, ParseOut::Cells(vec![vec![],
+        vec![Alive, Alive], vec![Alive, Alive]], PatternMetadata::default()));
+    assert_parse!(b"!Author: Someone\n!A block\nOO\nOO\n" => parse_file,
+        ParseOut::Cells(vec![vec![Alive, Alive]; 2], PatternMetadata {author:
+        Some("Someone".to_string()), comments: vec!["A block".to_string()],
+        ..PatternMetadata::default()}));
+    assert_parse!(b"! Name: Block \n! A comment\nOO\nOO\n" => parse_file,
+        ParseOut::Cells(vec![vec![Alive, Alive]; 2], PatternMetadata {name:
+        Some("Block".to_string()), comments: vec!["A comment".to_string()],
+        ..PatternMetadata::default()}));
+    assert_parse!(b"O.\n2o!\n" => parse_file, ParseOut::Fail);
+}
+
+#[test]
+fn test_parse_life() {
+    let cells = |coords: &[(i32, i32)]| ParseOut::CellList(coords.iter()
+        .map(|&(x, y)| (BigInt::from(x), BigInt::from(y)))
+        .collect(), PatternMetadata::default());
+
+    assert_parse!(b"#Life 1.06\n0 -1\n1 0\n-1 1\n0 1\n1 1\n" => parse_file,
+        cells(&[(0, -1), (1, 0), (-1, 1), (0, 1), (1, 1)]));
+    assert_parse!(b"#Life 1.06\n" => parse_file, cells(&[]));
+    assert_parse!(b"#Life 1.06\n12345678901234567890 0\n" => parse_file,
+        ParseOut::CellList(vec![("12345678901234567890".parse().unwrap(),
+        BigInt::from(0))], PatternMetadata::default()));
+    assert_parse!(b"1 2\n" => parse_file, ParseOut::Fail);
+
+    assert_parse!(b"#Life 1.05\n#D Glider\n#N\n#P -1 -1\n.*\n..*\n***\n"
+        => parse_file, cells(&[(0, -1), (1, 0), (-1, 1), (0, 1), (1, 1)]));
+    assert_parse!(b"#Life 1.05\n#P 10 0\n**\n#P -5 3\n.\n*.*\n" =>
+        parse_file, cells(&[(10, 0), (11, 0), (-5, 4), (-3, 4)]));
+    assert_parse!(b"#Life 1.05\n*\n" => parse_file, cells(&[(0, 0)]));
+    // Blank lines are rows within a block, but not before it.
+    assert_parse!(b"#Life 1.05\n\n#P 1 1\n*\n\n.*\n" => parse_file,
+        cells(&[(1, 1), (2, 3)]));
+    assert_parse!(b"#Life 1.05\n1 2\n" => parse_file, ParseOut::Fail);
+}
+
 #[test]
 fn test_parse_file() {
     use self::ParseOut::*;
     use self::RLEToken::*;
     use self::State::*;
+    let rule = PatternMetadata {rule: Some("B3/S23".to_string()),
+        ..PatternMetadata::default()};
 
     assert_parse!(b"x = 5, y = 5, rule = B3/S23\nobo$3bo!\n" => parse_file,
 //        vec![Run(1, Alive), Run(1, Dead), Run(1, Alive), EndLine, Run(3, Dead),
 //             Run(1, Alive), EndBlock]);
         RLE(vec![(1, State(Alive)), (1, State(Dead)), (1, State(Alive)), (1,
-             EndLine), (3, State(Dead)), (1, State(Alive)), (1, EndBlock)]));
+             EndLine), (3, State(Dead)), (1, State(Alive)), (1, EndBlock)],
+             rule.clone()));
     assert_parse!(b"x = 2, y = 2, rule = B3/S23\nbb$bb$!\n" => parse_file,
         RLE(vec![(1, State(Dead)), (1, State(Dead)), (1, EndLine), (1,
-            State(Dead)), (1, State(Dead)), (1, EndLine), (1, EndBlock)]));
+            State(Dead)), (1, State(Dead)), (1, EndLine), (1, EndBlock)],
+            rule));
+    assert_parse!(b"#N Glider\n#O Richard K. Guy\n#C The smallest ship\n\
+        #r B3/S23\nx = 3, y = 3\nbo$2bo$3o!\n" => parse_file, RLE(vec![(1,
+        State(Dead)), (1, State(Alive)), (1, EndLine), (2, State(Dead)), (1,
+        State(Alive)), (1, EndLine), (3, State(Alive)), (1, EndBlock)],
+        PatternMetadata {name: Some("Glider".to_string()), author:
+        Some("Richard K. Guy".to_string()), comments: vec![
+        "The smallest ship".to_string()], rule: Some("B3/S23".to_string()),
+        ..PatternMetadata::default()}));
+    // Eight `$`s in a row are only a macrocell leaf after a `[M2]` header.
+    assert_parse!(b"x = 1, y = 10\no\n$$$$$$$$$o!\n" => parse_file, RLE(vec![
+        (1, State(Alive)), (1, EndLine), (1, EndLine), (1, EndLine), (1,
+        EndLine), (1, EndLine), (1, EndLine), (1, EndLine), (1, EndLine), (1,
+        EndLine), (1, State(Alive)), (1, EndBlock)],
+        PatternMetadata::default()));
 }
 
 #[test]
@@ -372,8 +921,10 @@ fn debug() {
     println!("{:?}", parse_line(b"\n"));
     assert_parse!(b"!" => rle_line, expected);
     assert_parse!(b"!\n" => parse_line, LineParse::RLELine(expected.clone()));
-    assert_parse!(b"!\n" => parse_file, ParseOut::RLE(expected.clone()));
-    assert_parse!(b"!\n\n" => parse_file, ParseOut::RLE(expected.clone()));
+    assert_parse!(b"!\n" => parse_file, ParseOut::RLE(expected.clone(),
+        PatternMetadata::default()));
+    assert_parse!(b"!\n\n" => parse_file, ParseOut::RLE(expected.clone(),
+        PatternMetadata::default()));
 
     //const expected: MCNode = MCNode(4, 1, 1, 0, 1);
     assert_parse!(b"4 1 1 0 1" => mc_node, MCNode(4, 1, 1, 0, 1));
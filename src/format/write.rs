@@ -1,74 +1,250 @@
 use std::cmp;
+use std::collections::HashMap;
+use std::fmt::Display;
 
-use crate::Block;
+use num::{BigUint, ToPrimitive, Zero};
+
+use crate::{Block, LeafSize, LiveCells};
+use crate::global::Pattern;
 use crate::block::Block as RawBlock;
-use crate::leaf::{Leaf, LEAF_SIZE, LEAF_Y_SHIFT, LEAF_X_SHIFT};
-use super::parse::{RLEToken, RLEBuf, State};
+use super::parse::{PatternMetadata, RLEToken, RLEBuf, State};
+
+/// Transforms a block into RLE format, preceded by the name, author,
+/// comments, position and generation in `metadata` and with its rule in the
+/// header, so that `block_from_bytes` reads them back. Panics if the block
+/// is ill-formed.
+pub fn format_rle<L: LeafSize>(block: &Block<L>, metadata: &PatternMetadata)
+    -> String {
 
-/// Transforms a block into RLE format. Panics if the block is ill-formed.
-pub fn format_rle(block: &Block) -> String {
-    raw_format_rle(&block.to_raw())
+    let RLEData {rle, xsize, ysize} = cells_to_rle(LiveCells::new(
+        block.to_raw(), block.lg_size()));
+    rle_to_string(metadata_header(metadata, &xsize, &ysize), rle)
 }
 
 /// Raw version of `format_rle`. Used in implementation of Debug of
 /// `block::Block`.
-pub fn raw_format_rle(block: &RawBlock) -> String {
-    //let len = 1 << block.lg_size();
-    let _ = 1 << block.lg_size_verified().expect("Ill-formatted block");
-    rle_to_string(matrix_to_rle(block_to_matrix(block)))
-}
-
-fn block_to_matrix(block: &RawBlock) -> Vec<Vec<State>> {
-    match *block {
-        RawBlock::Leaf(leaf) => leaf_to_matrix(leaf).iter().map(|row|
-            row.to_vec()).collect(),
-        RawBlock::Node(node) => {
-            let corners = node.corners();
-            merge_rows(
-                merge_columns(block_to_matrix(&corners[0][0]),
-                              block_to_matrix(&corners[0][1])),
-                merge_columns(block_to_matrix(&corners[1][0]),
-                              block_to_matrix(&corners[1][1]))
-            )
+pub fn raw_format_rle<L: LeafSize>(block: &RawBlock<L>) -> String {
+    let lg_size = block.lg_size_verified().expect("Ill-formatted block");
+    let RLEData {rle, xsize, ysize} = cells_to_rle(LiveCells::new(*block,
+        lg_size));
+    rle_to_string(rle_header(&xsize, &ysize, "B3/S23"), rle)
+}
+
+/// Transforms a pattern into RLE format. Unlike `format_rle` only the
+/// bounding box of the pattern is written. Its position and generation are
+/// recorded in a `#CXRLE Pos=x,y Gen=g` line, leaving out the fields which
+/// are zero.
+pub fn format_pattern_rle<L: LeafSize>(pattern: &Pattern<L>) -> String {
+    let mut metadata = PatternMetadata::default();
+    let (rle, width, height) = match pattern.bounding_box() {
+        Some(bbox) => {
+            if !(bbox.x.is_zero() && bbox.y.is_zero()) {
+                metadata.offset = Some((bbox.x.clone(), bbox.y.clone()));
+            }
+            let RLEData {rle, ..} = cells_to_rle(pattern.live_cells().map(
+                |(x, y)| ((x - &bbox.x).to_biguint().unwrap(),
+                    (y - &bbox.y).to_biguint().unwrap())));
+            (rle, bbox.width, bbox.height)
         }
+        None => (vec![(1, RLEToken::EndBlock)], BigUint::zero(),
+            BigUint::zero()),
+    };
+    if !pattern.generation().is_zero() {
+        metadata.generation = Some(pattern.generation().clone());
     }
+    rle_to_string(metadata_header(&metadata, &width, &height), rle)
 }
 
-#[inline]
-fn leaf_to_matrix(leaf: Leaf) -> [[State; LEAF_SIZE]; LEAF_SIZE] {
-    #[inline]
-    fn bit(n: Leaf, bit: usize) -> State {
-        match (n >> bit) & 1 {
-            0 => State::Dead,
-            1 => State::Alive,
-            _ => unreachable!(),
-        }
+/// Transforms a block into plaintext (`.cells`) format, with `O` for live
+/// cells and `.` for dead cells. As with `format_rle` the rows start at the
+/// north-west corner of the block and dead cells after the last live cell
+/// of a row are left out. A blank block is written as a single dead cell,
+/// since an empty file isn't a pattern. Panics if the block is ill-formed.
+pub fn format_cells<L: LeafSize>(block: &Block<L>) -> String {
+    let raw = block.to_raw();
+    let lg_size = raw.lg_size_verified().expect("Ill-formatted block");
+    let res = cells_to_rows(LiveCells::new(raw, lg_size), 'O');
+    if res.is_empty() {".\n".to_string()} else {res}
+}
+
+/// Transforms a pattern into Life 1.06 format, which lists the coordinates
+/// of each live cell, row by row. Unlike the other formats the size of this
+/// only depends on the population, so it suits sparse patterns.
+pub fn format_life106<L: LeafSize>(pattern: &Pattern<L>) -> String {
+    let mut cells: Vec<_> = pattern.live_cells().map(|(x, y)| (y, x))
+        .collect();
+    cells.sort();
+    let mut res = "#Life 1.06\n".to_string();
+    for (y, x) in cells {
+        res.push_str(&format!("{} {}\n", x, y));
     }
+    res
+}
+
+/// Transforms a pattern into Life 1.05 format, as a single `#P` block of
+/// rows covering the bounding box of the pattern, with `*` for live cells
+/// and `.` for dead cells.
+pub fn format_life105<L: LeafSize>(pattern: &Pattern<L>) -> String {
+    let mut res = "#Life 1.05\n".to_string();
+    if let Some(bbox) = pattern.bounding_box() {
+        res.push_str(&format!("#P {} {}\n", bbox.x, bbox.y));
+        res.push_str(&cells_to_rows(pattern.live_cells().map(|(x, y)|
+            ((x - &bbox.x).to_biguint().unwrap(),
+                (y - &bbox.y).to_biguint().unwrap())), '*'));
+    }
+    res
+}
+
+/// Write the live cells `cells`, given as (x, y) coordinates, as rows of
+/// text with `alive` for live cells and `.` for dead cells, down to the last
+/// row with a live cell. Dead cells after the last live cell of a row are
+/// left out, so blank rows are empty lines. Panics if the coordinates don't
+/// fit in a `usize`.
+fn cells_to_rows<I>(cells: I, alive: char) -> String
+    where I: IntoIterator<Item = (BigUint, BigUint)> {
 
-    //[[bit(leaf, 0), bit(leaf, 1)], [bit(leaf, 4), bit(leaf, 5)]]
-    let mut res = [[State::Dead; LEAF_SIZE]; LEAF_SIZE];
-    for y in 0..LEAF_SIZE {
-        for x in 0..LEAF_SIZE {
-            res[y][x] = bit(leaf, y * LEAF_Y_SHIFT + x * LEAF_X_SHIFT);
+    let to_usize = |n: BigUint| n.to_usize().expect("Pattern too large to \
+        write as text rows");
+    let mut rows: Vec<Vec<char>> = Vec::new();
+    for (x, y) in cells {
+        let (x, y) = (to_usize(x), to_usize(y));
+        if rows.len() <= y {
+            rows.resize(y + 1, Vec::new());
         }
+        if rows[y].len() <= x {
+            rows[y].resize(x + 1, '.');
+        }
+        rows[y][x] = alive;
+    }
+
+    let mut res = String::new();
+    for row in rows {
+        res.extend(row);
+        res.push('\n');
     }
     res
 }
 
-fn merge_rows<A>(mut top: Vec<Vec<A>>, mut bottom: Vec<Vec<A>>) -> Vec<Vec<A>> {
-    top.append(&mut bottom);
-    top
+/// Transforms a block into Golly's macrocell (`[M2]`) format, preceded by
+/// `#R`, `#G` and `#C` lines for the rule, generation and comments in
+/// `metadata`. The rule is B3/S23 by default. Each distinct 8 by 8 block is
+/// written once as a line of rows, and each distinct larger node once as a
+/// line giving its size and the lines of its children, so this doesn't expand
+/// the block and works for exponentially large patterns. Blocks smaller than
+/// 8 by 8 are padded.
+pub fn format_mc<L: LeafSize>(block: &Block<L>, metadata: &PatternMetadata) ->
+    String {
+
+    let raw = block.to_raw();
+    let lg_size = block.lg_size();
+    let mut lines = Vec::new();
+    if mc_lines(raw, lg_size, &mut lines, &mut HashMap::new()) == 0 {
+        // Blank blocks are usually left out, but the block itself must have
+        // a line.
+        lines.push(if lg_size <= 3 {"$".repeat(8)} else {
+            format!("{} 0 0 0 0", lg_size)});
+    }
+
+    let rule = metadata.rule.as_ref().map_or("B3/S23", |rule| &rule[..]);
+    let mut res = format!("[M2] (hlife)\n#R {}\n", rule);
+    if let Some(ref generation) = metadata.generation {
+        res.push_str(&format!("#G {}\n", generation));
+    }
+    res.push_str(&comment_lines(&metadata.comments));
+    for line in lines {
+        res.push_str(&line);
+        res.push('\n');
+    }
+    res
 }
 
-fn merge_columns<A>(left: Vec<Vec<A>>, right: Vec<Vec<A>>) -> Vec<Vec<A>> {
-    debug_assert_eq!(left.len(), right.len());
+/// Add the macrocell lines for `block` and the blocks in it which aren't in
+/// `memo` yet to `lines`, and return the line number of `block`, counting
+/// from 1. Blank blocks aren't written and have number 0.
+// Nodes are hash-consed and hashed by structure, so their cells don't affect
+// their use as keys.
+#[allow(clippy::mutable_key_type)]
+fn mc_lines<'a, L: LeafSize>(block: RawBlock<'a, L>, lg_size: usize, lines:
+    &mut Vec<String>, memo: &mut HashMap<RawBlock<'a, L>, usize>) -> usize {
+
+    if block.is_blank() {
+        return 0;
+    }
+    if let Some(&index) = memo.get(&block) {
+        return index;
+    }
 
-    left.into_iter()
-        .zip(right)
-        .map(|(mut left_row, mut right_row)| {
-            left_row.append(&mut right_row);
-            left_row
-        }).collect()
+    let line = if lg_size <= 3 {
+        let mut rows = vec![String::new(); 8];
+        for (x, y) in LiveCells::new(block, lg_size) {
+            let row = &mut rows[y.to_usize().unwrap()];
+            let x = x.to_usize().unwrap();
+            while row.len() < x {
+                row.push('.');
+            }
+            row.push('*');
+        }
+        rows.iter().map(|row| format!("{}$", row)).collect()
+    } else {
+        let [[nw, ne], [sw, se]] = *block.unwrap_node().corners();
+        let children = [nw, ne, sw, se].map(|child| mc_lines(child,
+            lg_size - 1, lines, memo));
+        format!("{} {} {} {} {}", lg_size, children[0], children[1],
+            children[2], children[3])
+    };
+    lines.push(line);
+    memo.insert(block, lines.len());
+    lines.len()
+}
+
+fn rle_header<N: Display>(x: &N, y: &N, rule: &str) -> String {
+    format!("x = {}, y = {}, rule = {}\n", x, y, rule)
+}
+
+/// The lines of an RLE file before the pattern: `#N`, `#O` and `#C` lines
+/// for the name, author and comments, a `#CXRLE` line for the position and
+/// generation, and the header with the rule, which is B3/S23 by default.
+/// Line breaks in the name and author are written as spaces, and a comment
+/// with line breaks is written as a `#C` line for each of its lines.
+fn metadata_header<N: Display>(metadata: &PatternMetadata, x: &N, y: &N) ->
+    String {
+
+    let is_break = |c| c == '\n' || c == '\r';
+    let mut res = String::new();
+    if let Some(ref name) = metadata.name {
+        res.push_str(&format!("#N {}\n", name.replace(is_break, " ")));
+    }
+    if let Some(ref author) = metadata.author {
+        res.push_str(&format!("#O {}\n", author.replace(is_break, " ")));
+    }
+    res.push_str(&comment_lines(&metadata.comments));
+
+    let mut cxrle = Vec::new();
+    if let Some((ref x, ref y)) = metadata.offset {
+        cxrle.push(format!("Pos={},{}", x, y));
+    }
+    if let Some(ref generation) = metadata.generation {
+        cxrle.push(format!("Gen={}", generation));
+    }
+    if !cxrle.is_empty() {
+        res.push_str(&format!("#CXRLE {}\n", cxrle.join(" ")));
+    }
+
+    let rule = metadata.rule.as_ref().map_or("B3/S23", |rule| &rule[..]);
+    res.push_str(&rle_header(x, y, rule));
+    res
+}
+
+/// A `#C` line for each line of each comment in `comments`.
+fn comment_lines(comments: &[String]) -> String {
+    let mut res = String::new();
+    for comment in comments {
+        for line in comment.split('\n') {
+            let line = line.trim_end_matches('\r').replace('\r', " ");
+            res.push_str(&format!("#C {}\n", line));
+        }
+    }
+    res
 }
 
 struct RLEData {
@@ -77,53 +253,50 @@ struct RLEData {
     ysize: usize
 }
 
-fn matrix_to_rle(matrix: Vec<Vec<State>>) -> RLEData {
+/// Encode the live cells `cells`, given as (x, y) coordinates, as RLE. Only
+/// the live cells are visited, so this is cheap for sparse patterns even if
+/// they are spread over a large area. Panics if the coordinates don't fit in
+/// a `usize`.
+fn cells_to_rle<I>(cells: I) -> RLEData
+    where I: IntoIterator<Item = (BigUint, BigUint)> {
+
+    let to_usize = |n: BigUint| n.to_usize().expect("Pattern too large to \
+        write as RLE");
+    let mut cells: Vec<(usize, usize)> = cells.into_iter()
+        .map(|(x, y)| (to_usize(y), to_usize(x)))
+        .collect();
+    cells.sort();
+
     let mut res: RLEBuf = Vec::new();
-    let mut blank_lines = 0;
     let mut xmax = 0;
-    let mut ymax = 1;
-
-    for line in matrix {
-        let mut xlen = 0;
-        let mut run_val = State::Dead;
-        let mut run_len = 0;
-        let mut line_blank = true;
-
-        for state in line {
-            if state == run_val {
-                run_len += 1;
-            } else {
-                if line_blank && blank_lines > 0 {
-                    res.push((blank_lines, RLEToken::EndLine));
-                    ymax += blank_lines;
-                    blank_lines = 1;
-                    line_blank = false;
-                }
-                if run_len > 0 {
-                    res.push((run_len, RLEToken::State(run_val)));
-                    xlen += run_len;
-                }
-                run_val = state;
-                run_len = 1;
-            }
-        }
+    let mut ymax = 0;
+    // Position after the last token
+    let mut cur_x = 0;
+    let mut cur_y = 0;
 
-        if run_val != State::Dead {
-            res.push((run_len, RLEToken::State(run_val)));
-            xlen += run_len;
+    for (y, x) in cells {
+        if y > cur_y {
+            res.push((y - cur_y, RLEToken::EndLine));
+            cur_y = y;
+            cur_x = 0;
         }
-
-        if line_blank {
-            blank_lines += 1;
+        if x > cur_x {
+            res.push((x - cur_x, RLEToken::State(State::Dead)));
         }
-
-        xmax = cmp::max(xmax, xlen);
+        match res.last_mut() {
+            Some((len, RLEToken::State(State::Alive))) if x == cur_x =>
+                *len += 1,
+            _ => res.push((1, RLEToken::State(State::Alive))),
+        }
+        cur_x = x + 1;
+        xmax = cmp::max(xmax, x + 1);
+        ymax = y + 1;
     }
     res.push((1, RLEToken::EndBlock));
     RLEData {rle: res, xsize: xmax, ysize: ymax}
 }
 
-fn rle_to_string(rle_data: RLEData) -> String {
+fn rle_to_string(header: String, rle: RLEBuf) -> String {
     fn token_len_to_string(len: usize, token: RLEToken) -> String {
         let mut res = if len == 1 {String::new()} else {len.to_string()};
         res.push(match token {
@@ -135,9 +308,7 @@ fn rle_to_string(rle_data: RLEData) -> String {
         res
     }
 
-    let RLEData {rle, ysize: y, xsize: x} = rle_data;
-
-    let mut res = format!("x = {}, y = {}, rule = B3/S23\n", x, y);
+    let mut res = header;
     let mut line_len = 0;
 
     for (len, token) in rle {
@@ -158,8 +329,10 @@ fn rle_to_string(rle_data: RLEData) -> String {
 
 #[cfg(test)]
 mod test {
-    use super::format_rle;
-    use crate::Hashlife;
+    use super::{format_cells, format_life105, format_life106, format_mc,
+        format_rle, format_pattern_rle};
+    use super::super::parse::PatternMetadata;
+    use crate::{Hashlife, LeafSize, Leaf4x4};
 
     #[test]
     fn test_round_trip() {
@@ -168,40 +341,262 @@ mod test {
         Hashlife::with_new(|hl| {
             for &test in &tests {
                 let block = hl.rle(test);
-                let reformatted = format_rle(&block);
+                let reformatted = format_rle(&block,
+                    &PatternMetadata::default());
                 println!("{} -> {}", test, reformatted);
                 assert_eq!(Ok(block), hl.block_from_bytes(
-                    reformatted.as_bytes()));
+                    reformatted.as_bytes()).map(|(block, _)| block));
             }
         });
     }
 
+    fn metadata_round_trip<L: LeafSize>() {
+        use num::{BigInt, BigUint};
+
+        Hashlife::<L>::with_new_leaf(|hl| {
+            let rle = b"#N Glider\n#O Richard K. Guy\n#C The smallest\n\
+                #c spaceship\n#P -1 2\n#r B36/S23\nx = 3, y = 3\n\
+                bo$2bo$3o!\n";
+            let (block, metadata) = hl.block_from_bytes(rle).unwrap();
+            assert_eq!(metadata, PatternMetadata {
+                name: Some("Glider".to_string()),
+                author: Some("Richard K. Guy".to_string()),
+                comments: vec!["The smallest".to_string(),
+                    "spaceship".to_string()],
+                offset: Some((BigInt::from(-1), BigInt::from(2))),
+                rule: Some("B36/S23".to_string()),
+                generation: None,
+            });
+
+            let formatted = format_rle(&block, &metadata);
+            assert_eq!(formatted, "#N Glider\n#O Richard K. Guy\n\
+                #C The smallest\n#C spaceship\n#CXRLE Pos=-1,2\n\
+                x = 3, y = 3, rule = B36/S23\nbo$2bo$3o!\n");
+            assert_eq!(hl.block_from_bytes(formatted.as_bytes()),
+                Ok((block, metadata.clone())));
+
+            let stepped = PatternMetadata {generation:
+                Some(BigUint::from(4u8)), ..metadata};
+            let formatted = format_rle(&block, &stepped);
+            assert_eq!(hl.block_from_bytes(formatted.as_bytes()),
+                Ok((block, stepped)));
+
+            // Line breaks can't end up in the middle of a line
+            let multiline = PatternMetadata {
+                name: Some("Two\nlines".to_string()),
+                author: Some("A\r\nB".to_string()),
+                comments: vec!["First\r\nSecond\rline".to_string(),
+                    String::new()],
+                ..PatternMetadata::default()
+            };
+            let formatted = format_rle(&block, &multiline);
+            assert_eq!(formatted, "#N Two lines\n#O A  B\n#C First\n\
+                #C Second line\n#C \nx = 3, y = 3, rule = B3/S23\n\
+                bo$2bo$3o!\n");
+            assert_eq!(hl.block_from_bytes(formatted.as_bytes()),
+                Ok((block, PatternMetadata {
+                    name: Some("Two lines".to_string()),
+                    author: Some("A  B".to_string()),
+                    comments: vec!["First".to_string(),
+                        "Second line".to_string(), String::new()],
+                    rule: Some("B3/S23".to_string()),
+                    ..PatternMetadata::default()
+                })));
+        });
+    }
+
+    leaf_size_test!(test_metadata_round_trip, metadata_round_trip);
+
     // Test specific input-output pairs. Since I expect exact output will change
     // in later versions of this module this is not stable.
-    #[cfg(not(feature = "4x4_leaf"))]
     #[test]
-    fn test_instances() {
-        //if cfg!(features = "4x4_leaf")
-
+    fn test_instances_2x2() {
         Hashlife::with_new(|hl| {
             let b0 = hl.leaf(0x03);
-            assert_eq!(format_rle(&b0),
+            assert_eq!(format_rle(&b0, &PatternMetadata::default()),
                 "x = 2, y = 1, rule = B3/S23\n2o!\n");
             let b1 = hl.node_block([[b0, b0], [b0, b0]]);
-            assert_eq!(format_rle(&b1),
+            assert_eq!(format_rle(&b1, &PatternMetadata::default()),
                 "x = 4, y = 3, rule = B3/S23\n4o2$4o!\n");
         });
     }
 
-    #[cfg(feature = "4x4_leaf")]
     #[test]
-    fn test_instances() {
+    fn test_format_pattern_rle() {
+        use num::{BigInt, BigUint};
+        use crate::global::Pattern;
+
+        Hashlife::with_new(|hl| {
+            let blank = Pattern::new(hl.blank(5));
+            assert_eq!(format_pattern_rle(&blank),
+                "x = 0, y = 0, rule = B3/S23\n!\n");
+            let padded = Pattern::new(hl.rle("5$6b3o$6bo$7bo!"));
+            assert_eq!(format_pattern_rle(&padded),
+                "#CXRLE Pos=6,5\nx = 3, y = 3, rule = B3/S23\n3o$o$bo!\n");
+            let glider = Pattern::with_origin(hl.rle("3o$o$bo!"),
+                BigInt::from(-100), BigInt::from(7));
+            let formatted = format_pattern_rle(&glider);
+            assert_eq!(formatted,
+                "#CXRLE Pos=-100,7\nx = 3, y = 3, rule = B3/S23\n3o$o$bo!\n");
+            assert_eq!(hl.pattern_from_bytes(formatted.as_bytes()),
+                Some(glider.clone()));
+
+            let mut stepped = glider;
+            stepped.step(8);
+            let formatted = format_pattern_rle(&stepped);
+            assert_eq!(formatted, "#CXRLE Pos=-102,5 Gen=8\n\
+                x = 3, y = 3, rule = B3/S23\n3o$o$bo!\n");
+            let read = hl.pattern_from_bytes(formatted.as_bytes()).unwrap();
+            assert_eq!(read, stepped);
+            assert_eq!(read.generation(), stepped.generation());
+            let mut blank = blank;
+            blank.set_generation(BigUint::from(3u8));
+            assert_eq!(format_pattern_rle(&blank),
+                "#CXRLE Gen=3\nx = 0, y = 0, rule = B3/S23\n!\n");
+        });
+    }
+
+    #[test]
+    fn test_format_sparse() {
+        use num::{BigInt, One};
+        use crate::global::Pattern;
+
+        Hashlife::with_new(|hl| {
+            let big = BigInt::one() << 20usize;
+            let cells = vec![(BigInt::from(-3), BigInt::from(0)),
+                (big.clone(), BigInt::from(1)), (big.clone() + 1, BigInt::from(
+                1)), (BigInt::from(0), big.clone())];
+            let pattern = Pattern::from_cells(hl, cells);
+            let formatted = format_pattern_rle(&pattern);
+            assert_eq!(formatted,
+                "#CXRLE Pos=-3,0\nx = 1048581, y = 1048577, rule = B3/S23\n\
+                o$1048579b2o1048575$3bo!\n");
+        });
+    }
+
+    fn cells_round_trip<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            for &test in &["!", "5bo!", "2$o!", "bo$2bo$3o!", "2o$o3$2b2o!",
+                "24bo$22bobo$12b2o6b2o12b2o$11bo3bo4b2o12b2o$2o8bo5bo3b2o$2o8b\
+                o3bob2o4bobo$10bo5bo7bo$11bo3bo$12b2o!"] {
+                let block = hl.rle(test);
+                let formatted = format_cells(&block);
+                assert_eq!(hl.block_from_bytes(formatted.as_bytes())
+                    .map(|(block, _)| block), Ok(block), "{}", formatted);
+            }
+        });
+    }
+
+    leaf_size_test!(test_cells_round_trip, cells_round_trip);
+
+    #[test]
+    fn test_format_cells() {
+        Hashlife::with_new(|hl| {
+            assert_eq!(format_cells(&hl.rle("bo$2bo$3o!")), ".O\n..O\nOOO\n");
+            assert_eq!(format_cells(&hl.rle("$b2o$$o!")), "\n.OO\n\nO\n");
+            assert_eq!(format_cells(&hl.blank(3)), ".\n");
+        });
+    }
+
+    #[test]
+    fn test_format_life() {
+        use num::{BigInt, One};
+        use crate::global::Pattern;
+
+        Hashlife::with_new(|hl| {
+            let glider = Pattern::with_origin(hl.rle("bo$2bo$3o!"),
+                BigInt::from(-1), BigInt::from(-1));
+            assert_eq!(format_life106(&glider),
+                "#Life 1.06\n0 -1\n1 0\n-1 1\n0 1\n1 1\n");
+            assert_eq!(format_life105(&glider),
+                "#Life 1.05\n#P -1 -1\n.*\n..*\n***\n");
+
+            let blank = Pattern::new(hl.blank(3));
+            assert_eq!(format_life106(&blank), "#Life 1.06\n");
+            assert_eq!(format_life105(&blank), "#Life 1.05\n");
+
+            let big = BigInt::one() << 100usize;
+            let sparse = Pattern::from_cells(hl, vec![(-&big, big.clone()),
+                (BigInt::from(3), BigInt::from(0)), (big.clone(), -&big)]);
+            let spaced = Pattern::from_cells(hl, vec![(BigInt::from(2),
+                BigInt::from(-1)), (BigInt::from(0), BigInt::from(2))]);
+            for pattern in &[glider, blank, sparse, spaced] {
+                let life106 = format_life106(pattern);
+                assert_eq!(hl.pattern_from_bytes(life106.as_bytes()).as_ref(),
+                    Some(pattern));
+                let small = match pattern.bounding_box() {
+                    Some(bbox) => bbox.width < 100u8.into(),
+                    None => true,
+                };
+                if small {
+                    let life105 = format_life105(pattern);
+                    assert_eq!(hl.pattern_from_bytes(life105.as_bytes())
+                        .as_ref(), Some(pattern), "{}", life105);
+                }
+            }
+        });
+    }
+
+    fn mc_round_trip<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            let none = PatternMetadata::default();
+            let glider = hl.rle("bo$2bo$3o!");
+            let gun = hl.rle("24bo$22bobo$12b2o6b2o12b2o$11bo3bo4b2o12b2o$2o8b\
+                o5bo3b2o$2o8bo3bob2o4bobo$10bo5bo7bo$11bo3bo$12b2o!");
+            let mut huge = glider;
+            for _ in 0..100 {
+                let blank = hl.blank(huge.lg_size());
+                huge = hl.node_block([[huge, blank], [blank, huge]]);
+            }
+            for &block in &[hl.blank(3), hl.blank(7), hl.rle("7bo$$$$$$$o!"),
+                gun, huge, hl.node_block([[gun, gun], [gun, gun]])] {
+                let formatted = format_mc(&block, &none);
+                assert_eq!(hl.block_from_bytes(formatted.as_bytes())
+                    .map(|(block, _)| block), Ok(block), "{}", formatted);
+            }
+            // Each distinct node is written once: after the header there is
+            // a leaf and then a node of each size from 16 to 2^102.
+            assert_eq!(format_mc(&huge, &none).lines().count(), 2 + 1 + 99);
+            assert_eq!(format_mc(&glider, &none), "[M2] (hlife)\n#R B3/S23\n\
+                .*$..*$***$$$$$$\n");
+        });
+    }
+
+    leaf_size_test!(test_mc_round_trip, mc_round_trip);
+
+    #[test]
+    fn test_format_mc() {
         Hashlife::with_new(|hl| {
+            let none = PatternMetadata::default();
+            let glider = hl.rle("bo$2bo$3o!");
+            let pair = hl.rle("bo$2bo$3o6$9bo$10bo$8b3o!");
+            assert_eq!(format_mc(&pair, &none), "[M2] (hlife)\n#R B3/S23\n\
+                .*$..*$***$$$$$$\n4 1 0 0 1\n");
+            assert_eq!(format_mc(&hl.blank(5), &none),
+                "[M2] (hlife)\n#R B3/S23\n5 0 0 0 0\n");
+            assert_eq!(hl.block_from_bytes(format_mc(&glider, &none).as_bytes())
+                .map(|(block, _)| block), Ok(hl.rle("bo$2bo$3o5$8b!")));
+
+            // The rule, generation and comments are kept.
+            let mc = b"[M2] (golly 4.2)\n#R B36/S23\n#G 1000\n#C First\n\
+                #C Second\n.*$..*$***$$$$$$\n";
+            let (block, metadata) = hl.block_from_bytes(mc).unwrap();
+            let formatted = format_mc(&block, &metadata);
+            assert_eq!(formatted, "[M2] (hlife)\n#R B36/S23\n#G 1000\n\
+                #C First\n#C Second\n.*$..*$***$$$$$$\n");
+            assert_eq!(hl.block_from_bytes(formatted.as_bytes()),
+                Ok((block, metadata)));
+        });
+    }
+
+    #[test]
+    fn test_instances_4x4() {
+        Hashlife::<Leaf4x4>::with_new_leaf(|hl| {
             let b0 = hl.leaf(0x000f);
-            assert_eq!(format_rle(&b0),
+            assert_eq!(format_rle(&b0, &PatternMetadata::default()),
                 "x = 4, y = 1, rule = B3/S23\n4o!\n");
             let b1 = hl.node_block([[b0, b0], [b0, b0]]);
-            assert_eq!(format_rle(&b1),
+            assert_eq!(format_rle(&b1, &PatternMetadata::default()),
                 "x = 8, y = 5, rule = B3/S23\n8o4$8o!\n");
         });
     }
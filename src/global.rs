@@ -1,130 +1,1605 @@
+use std::cmp::{min, max, Ordering};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 
-use num::{BigUint, One, FromPrimitive, Zero};
+use num::{BigInt, BigUint, One, FromPrimitive, Integer, Signed, ToPrimitive,
+    Zero};
 
-use crate::{Block, Hashlife};
+use crate::{Block, BoolOp, Hashlife, LeafSize, Leaf2x2, Symmetry};
+use crate::block::{Block as RawBlock, Node as RawNode};
+use crate::leaf::{LEAF_Y_SHIFT, LEAF_X_SHIFT};
 use crate::util::{log2_upper_bigu, make_2x2};
 
+/// Rectangle of cells whose north-west corner is (x, y).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub x: BigInt,
+    pub y: BigInt,
+    pub width: BigUint,
+    pub height: BigUint,
+}
+
+/// A pattern which reappears moved by (dx, dy) after `period` generations.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Spaceship {
+    pub period: u64,
+    pub dx: BigInt,
+    pub dy: BigInt,
+}
+
+impl Spaceship {
+    /// The speed in the usual notation, such as `c/4 diagonal`, `2c/5
+    /// orthogonal`, or `(2,1)c/6` for an oblique spaceship. The fraction is
+    /// reduced, so the LWSS, which moves 2 cells in its period of 4, is
+    /// `c/2 orthogonal`.
+    pub fn speed(&self) -> String {
+        let (dx, dy) = (self.dx.abs(), self.dy.abs());
+        let (long, short) = (max(&dx, &dy), min(&dx, &dy));
+        let divisor = long.gcd(short).gcd(&BigInt::from(self.period));
+        let (long, short) = (long / &divisor, short / &divisor);
+        let period = BigInt::from(self.period) / &divisor;
+        let dist = if long.is_one() {String::new()} else {long.to_string()};
+        if short.is_zero() {
+            format!("{}c/{} orthogonal", dist, period)
+        } else if short == long {
+            format!("{}c/{} diagonal", dist, period)
+        } else {
+            format!("({},{})c/{}", long, short, period)
+        }
+    }
+}
+
+/// Long-term behaviour of a pattern, as found by `Pattern::classify`.
+/// Generations are counted from the start of the classification.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Classification {
+    /// All cells are dead from `generation` on.
+    Dies {generation: u64},
+    /// From `generation` on the pattern is a still life (of period 1) or an
+    /// oscillator.
+    Oscillator {period: u64, generation: u64},
+    /// From `generation` on the whole pattern is a spaceship.
+    Spaceship {ship: Spaceship, generation: u64},
+    /// From `generation` on the population is periodic with period
+    /// `period`, but the pattern never repeats. Typically, the pattern has
+    /// stabilised except for spaceships flying off.
+    EmitsSpaceships {period: u64, generation: u64},
+    /// The population keeps growing.
+    Grows(Growth),
+    /// None of the above was found within the generation limit.
+    Unknown,
+}
+
+/// Rate of unbounded population growth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Growth {
+    /// Like a gun, emitting a constant stream of spaceships
+    Linear,
+    /// Like a breeder
+    Superlinear,
+}
+
+/// How `Pattern::paste` combines the pasted pattern with the existing one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PasteMode {
+    /// Replace the cells in the bounding box of the pasted pattern
+    Copy,
+    Or,
+    Xor,
+    /// Kill the cells which are alive in the pasted pattern
+    AndNot,
+}
+
 /// Infinite pattern which is dead in all but a finite area.
-#[derive(Debug)]
-pub struct Pattern<'a> {
-    block: Block<'a>,
-    dead_space: BigUint,
+///
+/// The pattern has absolute coordinates, with x increasing to the east and y
+/// increasing to the south, and they stay fixed as the pattern is stepped.
+/// The pattern also counts the generations it has been stepped.
+#[derive(Clone, Debug)]
+pub struct Pattern<'a, L: LeafSize = Leaf2x2> {
+    block: Block<'a, L>,
+    // Coordinates of the north-west corner of `block`
+    x: BigInt,
+    y: BigInt,
+    generation: BigUint,
 }
 
-impl<'a> Pattern<'a> {
-    // `block` must be a node
-    pub fn new(block: Block<'a>) -> Self {
-        assert!(block.destruct().is_ok(), "Pattern block must be a node");
-        Pattern {block: block, dead_space: BigUint::zero()}
+impl<'a, L: LeafSize> Pattern<'a, L> {
+    /// Create a pattern with the north-west corner of `block` at the origin.
+    pub fn new(block: Block<'a, L>) -> Self {
+        Pattern::with_origin(block, BigInt::zero(), BigInt::zero())
+    }
+
+    /// Create a pattern whose live cells are `cells`, given as (x, y)
+    /// coordinates. Repeated cells are allowed.
+    pub fn from_cells<I>(hl: Hashlife<'a, L>, cells: I) -> Self
+        where I: IntoIterator<Item = (BigInt, BigInt)> {
+
+        let cells: Vec<(BigInt, BigInt)> = cells.into_iter().collect();
+        let (x, y) = match (cells.iter().map(|c| &c.0).min(),
+            cells.iter().map(|c| &c.1).min()) {
+            (Some(x), Some(y)) => (x.clone(), y.clone()),
+            _ => return Pattern::new(hl.blank(L::LG_LEAF_SIZE + 1)),
+        };
+        let cells: Vec<(BigUint, BigUint)> = cells.into_iter()
+            .map(|(cx, cy)| ((cx - &x).to_biguint().unwrap(),
+                (cy - &y).to_biguint().unwrap()))
+            .collect();
+        let side = cells.iter().map(|c| max(&c.0, &c.1)).max().unwrap() + 1u8;
+        let lg_size = max(log2_upper_bigu(&side) as usize, L::LG_LEAF_SIZE);
+
+        fn build<'a, L: LeafSize>(hl: &Hashlife<'a, L>, cells: Vec<(BigUint,
+            BigUint)>, lg_size: usize) -> RawBlock<'a, L> {
+
+            if cells.is_empty() {
+                hl.raw_blank(lg_size)
+            } else if lg_size == L::LG_LEAF_SIZE {
+                RawBlock::Leaf(cells.iter().fold(0, |leaf, (x, y)| leaf
+                    | 1 << (y.to_usize().unwrap() * LEAF_Y_SHIFT
+                        + x.to_usize().unwrap() * LEAF_X_SHIFT)))
+            } else {
+                let half = BigUint::one() << (lg_size - 1);
+                let mut quadrants = [[Vec::new(), Vec::new()], [Vec::new(),
+                    Vec::new()]];
+                for (x, y) in cells {
+                    let (i, y) = if y < half {(0, y)} else {(1, y - &half)};
+                    let (j, x) = if x < half {(0, x)} else {(1, x - &half)};
+                    quadrants[i][j].push((x, y));
+                }
+                let [[q00, q01], [q10, q11]] = quadrants;
+                hl.raw_node_block([
+                    [build(hl, q00, lg_size - 1), build(hl, q01, lg_size - 1)],
+                    [build(hl, q10, lg_size - 1), build(hl, q11, lg_size - 1)],
+                ])
+            }
+        }
+
+        let raw = build(&hl, cells, lg_size);
+        Pattern::with_origin(hl.block_from_raw_sized(raw, lg_size), x, y)
+    }
+
+    /// Create a pattern with the north-west corner of `block` at (x, y), at
+    /// generation 0.
+    pub fn with_origin(block: Block<'a, L>, x: BigInt, y: BigInt) -> Self {
+        let generation = BigUint::zero();
+        let mut pattern = if block.destruct().is_ok() {
+            Pattern {block, x, y, generation}
+        } else {
+            // Leafs are encased so that the pattern block is always a node.
+            let hl = block.hashlife_instance();
+            let half_leaf = L::LEAF_SIZE / 2;
+            Pattern {
+                block: encase(hl, block),
+                x: x - half_leaf,
+                y: y - half_leaf,
+                generation,
+            }
+        };
+        pattern.shrink();
+        pattern
     }
 
-    pub fn block(&self) -> Block<'a> {
+    pub fn block(&self) -> Block<'a, L> {
         self.block
     }
 
+    /// Number of live cells in the pattern.
+    pub fn population(&self) -> BigUint {
+        self.block.population()
+    }
+
+    /// Smallest rectangle containing all live cells of the pattern, or `None`
+    /// if it has none. Memoised in each node like `population`.
+    pub fn bounding_box(&self) -> Option<Rect> {
+        self.block.to_raw().bounds().map(|bounds| Rect {
+            x: &self.x + BigInt::from(bounds.x_min.clone()),
+            y: &self.y + BigInt::from(bounds.y_min.clone()),
+            width: bounds.x_max - bounds.x_min,
+            height: bounds.y_max - bounds.y_min,
+        })
+    }
+
+    /// Coordinates of the north-west corner of `self.block()`.
+    pub fn origin(&self) -> (&BigInt, &BigInt) {
+        (&self.x, &self.y)
+    }
+
+    /// Number of generations the pattern has been stepped, starting from the
+    /// generation it was created or last set at.
+    pub fn generation(&self) -> &BigUint {
+        &self.generation
+    }
+
+    pub fn set_generation(&mut self, generation: BigUint) {
+        self.generation = generation;
+    }
+
+    /// Advance the pattern `nsteps` generations. Steps by a power of two are
+    /// memoised in the nodes, so running a pattern with a constant such
+    /// increment only computes each distinct node's future once.
     pub fn step(&mut self, nsteps: u64) {
         self.step_bigu(&BigUint::from_u64(nsteps).unwrap())
     }
 
     pub fn step_bigu(&mut self, nsteps: &BigUint) {
-        let new_length = self.length_bigu() + (nsteps << 1);
-        let lg_size_needed = log2_upper_bigu(&new_length) as usize + 1;
-        let mut block = self.block;
-        while block.lg_size() < lg_size_needed {
-            block = encase(self.hl(), block);
+        if nsteps.is_zero() {
+            return;
+        }
+        self.encase_for_step(nsteps);
+        let stepped = self.hl().step_bigu(self.block.unwrap_node(), nsteps);
+        self.finish_step(stepped, nsteps);
+    }
+
+    /// Advance the pattern `nsteps` generations in the manner of Golly's
+    /// hyperspeed mode. Stepping starts one generation at a time, and the
+    /// step size is doubled after each step which added at most
+    /// `max_new_nodes` nodes to the block cache, so that patterns whose
+    /// evolution is regular can be run for astronomically many generations.
+    /// Every step is by a power of two, except that the last one is
+    /// shortened as needed to stop after exactly `nsteps` generations.
+    ///
+    /// `on_step` is called with the pattern after each step, which can be
+    /// used to sample the population as it grows.
+    pub fn run_hyper<F>(&mut self, nsteps: &BigUint, max_new_nodes: usize,
+        mut on_step: F) where F: FnMut(&Self) {
+
+        let mut remaining = nsteps.clone();
+        let mut lognsteps = 0;
+        while !remaining.is_zero() {
+            // Never step past the end
+            lognsteps = min(lognsteps, remaining.bits() as usize - 1);
+            let nodes_before = self.hl().block_cache().len();
+            self.step_pow2(lognsteps);
+            let new_nodes = self.hl().block_cache().len() - nodes_before;
+            remaining -= BigUint::one() << lognsteps;
+            on_step(self);
+            if new_nodes <= max_new_nodes {
+                lognsteps += 1;
+            }
+        }
+    }
+
+    /// Replace the block of the pattern by the smallest block containing all
+    /// live cells, with its north-west corner at that of their bounding box.
+    /// A blank pattern is left with a blank node of leafs at its center. This
+    /// is done automatically on creation and after each step.
+    pub fn shrink(&mut self) {
+        match self.bounding_box() {
+            Some(bbox) => {
+                self.block = self.bbox_block(&bbox);
+                self.x = bbox.x;
+                self.y = bbox.y;
+            }
+            None => {
+                // The block is always kept a node, so stop at nodes of leafs.
+                let lg_size = L::LG_LEAF_SIZE + 1;
+                let lg_side = self.block.lg_size();
+                if lg_side > lg_size {
+                    let offset = (BigInt::one() << (lg_side - 1))
+                        - (BigInt::one() << (lg_size - 1));
+                    self.x += &offset;
+                    self.y += &offset;
+                    self.block = self.hl().blank(lg_size);
+                }
+            }
         }
-        self.block = self.hl().step_bigu(block.unwrap_node(), nsteps);
-        self.dead_space = (BigUint::one() << self.block.lg_size()) - new_length;
     }
 
-/*
+    /// Advance the pattern `2^lognsteps` generations. Unlike `step_bigu`
+    /// this goes straight to the memoised `Hashlife::step_pow2`.
     fn step_pow2(&mut self, lognsteps: usize) {
-        self.step_pow2_1(lognsteps);
+        let nsteps = BigUint::one() << lognsteps;
+        self.encase_for_step(&nsteps);
+        let stepped = self.hl().step_pow2(self.block.unwrap_node(),
+            lognsteps);
+        self.finish_step(stepped, &nsteps);
     }
 
-    fn step_pow2_1(&mut self, lognsteps: usize) {
-        let new_length = self.length() + (1 << (1 + lognsteps));
-        let lgsize_needed = log2_upper(new_length) as usize;
-        while self.block.lg_size() < lgsize_needed {
+    /// Encase the block until stepping it `nsteps` generations gives the
+    /// whole future pattern.
+    fn encase_for_step(&mut self, nsteps: &BigUint) {
+        // Live cells spread at most `nsteps` cells past the current block.
+        // Since the block stays centered as it is encased, the stepped node
+        // must be large enough that this fits in the margin around the
+        // block in its center. Also, `nsteps` must be less than a quarter of
+        // the node's side length.
+        let lg_size = self.block.lg_size();
+        let margin = |lg: usize| ((BigUint::one() << (lg - 1)) -
+            (BigUint::one() << lg_size)) >> 1;
+        while self.block.lg_size() <= lg_size
+            || self.block.lg_size() < nsteps.bits() as usize + 2
+            || margin(self.block.lg_size()) < *nsteps {
             self.encase();
         }
-        let reencase = encase(self.hl(), self.block);
-        self.block = self.hl().step_pow2(reencase.unwrap_node(), lognsteps);
     }
 
-    fn encase(&mut self) {
+    /// Replace the encased block by `stepped`, the center of its future
+    /// `nsteps` generations later.
+    fn finish_step(&mut self, stepped: Block<'a, L>, nsteps: &BigUint) {
+        let quarter = BigInt::one() << (self.block.lg_size() - 2);
+        self.block = stepped;
+        self.x += &quarter;
+        self.y += &quarter;
+        self.generation += nsteps;
+        self.shrink();
+    }
+
+    /// Return the `2^lg_size` by `2^lg_size` block of the pattern whose
+    /// north-west corner is at (x, y).
+    pub fn block_at(&self, x: &BigInt, y: &BigInt, lg_size: usize) ->
+        Block<'a, L> {
+
+        let hl = self.hl();
+        let size = BigInt::one() << lg_size;
+        if x + &size <= self.x || &self.x + self.side() <= *x
+            || y + &size <= self.y || &self.y + self.side() <= *y {
+            return hl.blank(lg_size);
+        }
+
+        // Encase until the block contains the requested one and is larger.
+        let mut expanded = self.clone();
+        while expanded.block.lg_size() <= lg_size || *x < expanded.x
+            || *y < expanded.y || x + &size > &expanded.x + expanded.side()
+            || y + &size > &expanded.y + expanded.side() {
+            expanded.encase();
+        }
+
+        let dx = (x - &expanded.x).to_biguint().unwrap();
+        let dy = (y - &expanded.y).to_biguint().unwrap();
+        let raw = window(&hl, expanded.block.unwrap_node().to_raw(),
+            expanded.block.lg_size(), dx, dy, lg_size, &mut HashMap::new());
+        hl.block_from_raw_sized(raw, lg_size)
+    }
+
+    /// Iterator over the coordinates (x, y) of the live cells of the pattern.
+    /// See `Block::live_cells`.
+    pub fn live_cells(&self) -> impl Iterator<Item = (BigInt, BigInt)> + 'a {
+        let (x, y) = (self.x.clone(), self.y.clone());
+        self.block.live_cells().map(move |(dx, dy)| (&x + BigInt::from(dx),
+            &y + BigInt::from(dy)))
+    }
+
+    /// Whether the cell at (x, y) is alive.
+    pub fn get_cell(&self, x: &BigInt, y: &BigInt) -> bool {
+        if *x < self.x || *y < self.y || &self.x + self.side() <= *x
+            || &self.y + self.side() <= *y {
+            return false;
+        }
+
+        let mut dx = (x - &self.x).to_biguint().unwrap();
+        let mut dy = (y - &self.y).to_biguint().unwrap();
+        let mut block = self.block.to_raw();
+        let mut lg_size = self.block.lg_size();
+        while let RawBlock::Node(n) = block {
+            lg_size -= 1;
+            let i = (&dy >> lg_size).to_usize().unwrap();
+            let j = (&dx >> lg_size).to_usize().unwrap();
+            dy -= BigUint::from(i) << lg_size;
+            dx -= BigUint::from(j) << lg_size;
+            block = n.corners()[i][j];
+        }
+        let shift = dy.to_usize().unwrap() * LEAF_Y_SHIFT
+            + dx.to_usize().unwrap() * LEAF_X_SHIFT;
+        (block.unwrap_leaf() >> shift) & 1 == 1
+    }
+
+    /// Set the cell at (x, y) to be alive or dead. Only the nodes on the path
+    /// to the cell are rebuilt, and the block is expanded if the cell lies
+    /// outside of it.
+    pub fn set_cell(&mut self, x: &BigInt, y: &BigInt, alive: bool) {
+        if self.get_cell(x, y) == alive {
+            return;
+        }
+        while *x < self.x || *y < self.y || &self.x + self.side() <= *x
+            || &self.y + self.side() <= *y {
+            self.encase();
+        }
+
+        fn set<'a, L: LeafSize>(hl: &Hashlife<'a, L>, block: RawBlock<'a, L>,
+            lg_size: usize, dx: BigUint, dy: BigUint, alive: bool) ->
+            RawBlock<'a, L> {
+
+            match block {
+                RawBlock::Leaf(l) => {
+                    let bit = 1 << (dy.to_usize().unwrap() * LEAF_Y_SHIFT
+                        + dx.to_usize().unwrap() * LEAF_X_SHIFT);
+                    RawBlock::Leaf(if alive {l | bit} else {l & !bit})
+                }
+                RawBlock::Node(n) => {
+                    let lg_size = lg_size - 1;
+                    let i = (&dy >> lg_size).to_usize().unwrap();
+                    let j = (&dx >> lg_size).to_usize().unwrap();
+                    let mut corners = *n.corners();
+                    corners[i][j] = set(hl, corners[i][j], lg_size,
+                        dx - (BigUint::from(j) << lg_size),
+                        dy - (BigUint::from(i) << lg_size), alive);
+                    hl.raw_node_block(corners)
+                }
+            }
+        }
+
+        let dx = (x - &self.x).to_biguint().unwrap();
+        let dy = (y - &self.y).to_biguint().unwrap();
+        let hl = self.hl();
+        let lg_size = self.block.lg_size();
+        let raw = set(&hl, self.block.to_raw(), lg_size, dx, dy, alive);
+        self.block = hl.block_from_raw_sized(raw, lg_size);
+        self.shrink();
+    }
+
+    /// Apply `op` to the two patterns cell by cell. See `Block::bool_op`.
+    /// The result has the generation of `self`.
+    pub fn bool_op(&self, op: BoolOp, other: &Self) -> Self {
+        let (x, y, lg_size) = self.common_frame(other);
+        let block = self.block_at(&x, &y, lg_size).bool_op(op,
+            &other.block_at(&x, &y, lg_size));
+        self.with_block(block, x, y)
+    }
+
+    /// Cells alive in either pattern.
+    pub fn union(&self, other: &Self) -> Self {
+        self.bool_op(BoolOp::Union, other)
+    }
+
+    /// Cells alive in both patterns.
+    pub fn intersection(&self, other: &Self) -> Self {
+        self.bool_op(BoolOp::Intersection, other)
+    }
+
+    /// Cells alive in `self` but not in `other`.
+    pub fn difference(&self, other: &Self) -> Self {
+        self.bool_op(BoolOp::Difference, other)
+    }
+
+    /// Cells alive in exactly one of the patterns.
+    pub fn xor(&self, other: &Self) -> Self {
+        self.bool_op(BoolOp::Xor, other)
+    }
+
+    /// Apply the symmetry `sym` to the pattern, fixing the cell at the
+    /// origin. For example, `Symmetry::Rotate90` maps the cell at (x, y) to
+    /// (-y, x).
+    pub fn transform(&self, sym: Symmetry) -> Self {
+        let (swap, neg_x, neg_y) = sym.as_signed_permutation();
+        let last: BigInt = self.side() - 1;
+        let (x, y) = if swap {(&self.y, &self.x)} else {(&self.x, &self.y)};
+        Pattern {
+            block: self.block.transform(sym),
+            x: if neg_x {-(x + &last)} else {x.clone()},
+            y: if neg_y {-(y + &last)} else {y.clone()},
+            generation: self.generation.clone(),
+        }
+    }
+
+    /// Move the pattern `dx` cells east and `dy` cells south.
+    pub fn translate(&self, dx: &BigInt, dy: &BigInt) -> Self {
+        Pattern {
+            block: self.block,
+            x: &self.x + dx,
+            y: &self.y + dy,
+            generation: self.generation.clone(),
+        }
+    }
+
+    /// The pattern translated so that the north-west corner of its bounding
+    /// box is at the origin. Two patterns have the same canonical form
+    /// exactly when one is a translation of the other, so this is suitable
+    /// for deduplicating objects with a `HashSet`.
+    pub fn canonical(&self) -> Self {
+        let block = match self.bounding_box() {
+            Some(bbox) => self.bbox_block(&bbox),
+            None => self.hl().blank(L::LG_LEAF_SIZE + 1),
+        };
+        self.with_block(block, BigInt::zero(), BigInt::zero())
+    }
+
+    /// Like `canonical`, but also normalised over the symmetries of the
+    /// square: patterns have the same result exactly when one can be mapped
+    /// to the other by a translation and a `Symmetry`. The choice among the
+    /// transformed patterns doesn't depend on the `Hashlife` instance, so it
+    /// is stable between runs.
+    pub fn canonical_d4(&self) -> Self {
+        Symmetry::ALL.iter()
+            .map(|&sym| self.transform(sym).canonical())
+            .min_by(|a, b| structural_cmp(a.block.to_raw(), b.block.to_raw()))
+            .unwrap()
+    }
+
+    /// The period of the pattern if it is a still life or an oscillator of
+    /// period at most `max_gens`, that is the number of generations until it
+    /// is first the same again. Still lives, and the empty pattern, have
+    /// period 1. The pattern is stepped one generation at a time, and since
+    /// nodes are hash-consed and a stepped pattern is already shrunk to its
+    /// bounding box, comparing each generation with the first one takes
+    /// constant time apart from finding the bounding box of the nodes made
+    /// by the step.
+    pub fn detect_period(&self, max_gens: u64) -> Option<u64> {
+        match self.recurrence(max_gens) {
+            Some((period, dx, dy)) if dx.is_zero() && dy.is_zero() =>
+                Some(period),
+            _ => None,
+        }
+    }
+
+    /// Detect whether the pattern is a spaceship, which is the same as
+    /// itself moved by some nonzero displacement after at most `max_gens`
+    /// generations. Like `detect_period` this takes constant time per
+    /// generation apart from the stepping and finding the bounding box of
+    /// the nodes it makes.
+    pub fn detect_spaceship(&self, max_gens: u64) -> Option<Spaceship> {
+        match self.recurrence(max_gens) {
+            Some((period, dx, dy)) if !(dx.is_zero() && dy.is_zero()) =>
+                Some(Spaceship {period, dx, dy}),
+            _ => None,
+        }
+    }
+
+    /// Advance a copy of the pattern up to `limit` generations one at a time
+    /// to find out how it behaves in the long term. Every generation is
+    /// remembered by its normalised block, so the pattern is found to be an
+    /// oscillator or spaceship as soon as some generation repeats, even if
+    /// this is not the first one.
+    ///
+    /// Failing that, the population over the second half of the run is
+    /// checked for periodicity, and otherwise its growth is estimated from
+    /// how fast the population increases at `limit / 4`, `limit / 2` and
+    /// `limit` generations. These are heuristics, and a large enough `limit`
+    /// is needed for a pattern to settle down before they are reliable.
+    // Nodes are hash-consed and hashed by structure, so their cells don't
+    // affect their use as keys.
+    #[allow(clippy::mutable_key_type)]
+    pub fn classify(&self, limit: u64) -> Classification {
+        let mut seen = HashMap::new();
+        let mut populations = Vec::new();
+        let mut pattern = self.clone();
+        for gen in 0..=limit {
+            if gen > 0 {
+                pattern.step(1);
+            }
+            let (x, y, block) = match pattern.normalized() {
+                Some(normalized) => normalized,
+                None => return Classification::Dies {generation: gen},
+            };
+            if let Some((start, x0, y0)) = seen.insert(block, (gen, x.clone(),
+                y.clone())) {
+
+                let period = gen - start;
+                let (dx, dy) = (x - x0, y - y0);
+                return if dx.is_zero() && dy.is_zero() {
+                    Classification::Oscillator {period, generation: start}
+                } else {
+                    let ship = Spaceship {period, dx, dy};
+                    Classification::Spaceship {ship, generation: start}
+                };
+            }
+            populations.push(pattern.population());
+        }
+
+        if let Some((period, generation)) = eventual_period(&populations) {
+            Classification::EmitsSpaceships {period, generation}
+        } else if let Some(growth) = population_growth(&populations) {
+            Classification::Grows(growth)
+        } else {
+            Classification::Unknown
+        }
+    }
+
+    /// The first generation, up to `max_gens`, at which the pattern is a
+    /// translation of itself, and the displacement.
+    fn recurrence(&self, max_gens: u64) -> Option<(u64, BigInt, BigInt)> {
+        let start = self.normalized();
+        let mut pattern = self.clone();
+        for gen in 1..=max_gens {
+            pattern.step(1);
+            match (&start, pattern.normalized()) {
+                (None, None) => return Some((gen, BigInt::zero(),
+                    BigInt::zero())),
+                (Some((x0, y0, b0)), Some((x, y, b))) if *b0 == b =>
+                    return Some((gen, x - x0, y - y0)),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// The part of the pattern inside `rect`.
+    pub fn extract(&self, rect: &Rect) -> Self {
+        let block = self.crop(rect, true);
+        self.with_block(block, self.x.clone(), self.y.clone())
+    }
+
+    /// Kill all cells inside `rect`.
+    pub fn clear(&mut self, rect: &Rect) {
+        self.block = self.crop(rect, false);
+        self.shrink();
+    }
+
+    /// Paste `other`, moved by (dx, dy) = `at`, onto the pattern.
+    pub fn paste(&mut self, other: &Self, at: (&BigInt, &BigInt), mode:
+        PasteMode) {
+
+        let moved = other.translate(at.0, at.1);
+        *self = match mode {
+            PasteMode::Copy => {
+                if let Some(bbox) = moved.bounding_box() {
+                    self.clear(&bbox);
+                }
+                self.union(&moved)
+            }
+            PasteMode::Or => self.union(&moved),
+            PasteMode::Xor => self.xor(&moved),
+            PasteMode::AndNot => self.difference(&moved),
+        };
+    }
+
+    /// The block of the pattern with the cells outside of `rect` killed if
+    /// `inside`, or the cells inside of it killed otherwise.
+    fn crop(&self, rect: &Rect, inside: bool) -> Block<'a, L> {
+        // The rectangle relative to the block, clipped to it
+        let side = self.side();
+        let clip = |n: BigInt| max(min(n, side.clone()), BigInt::zero())
+            .to_biguint().unwrap();
+        let x0 = &rect.x - &self.x;
+        let y0 = &rect.y - &self.y;
+        let x1 = &x0 + BigInt::from(rect.width.clone());
+        let y1 = &y0 + BigInt::from(rect.height.clone());
+        let bounds = [clip(x0), clip(y0), clip(x1), clip(y1)];
+
+        let hl = self.hl();
         let lg_size = self.block.lg_size();
+        let raw = crop(&hl, self.block.to_raw(), lg_size, bounds, inside,
+            &mut HashMap::new());
+        hl.block_from_raw_sized(raw, lg_size)
+    }
+
+    /// A pattern with the same generation as `self` made from `block` placed
+    /// at (x, y).
+    fn with_block(&self, block: Block<'a, L>, x: BigInt, y: BigInt) -> Self {
+        let mut pattern = Pattern::with_origin(block, x, y);
+        pattern.generation = self.generation.clone();
+        pattern
+    }
+
+    /// The smallest block whose north-west corner is that of the bounding box
+    /// `bbox` of the pattern and which contains it. This is the block of the
+    /// pattern itself after `shrink`, which is returned without rebuilding it.
+    fn bbox_block(&self, bbox: &Rect) -> Block<'a, L> {
+        let side = max(&bbox.width, &bbox.height);
+        let lg_size = max(log2_upper_bigu(side) as usize,
+            L::LG_LEAF_SIZE + 1);
+        if self.x == bbox.x && self.y == bbox.y
+            && self.block.lg_size() == lg_size {
+            return self.block;
+        }
+        self.block_at(&bbox.x, &bbox.y, lg_size)
+    }
+
+    /// The north-west corner of the bounding box of the pattern together with
+    /// the raw `bbox_block` there, or `None` if the pattern is blank. Two
+    /// patterns are translations of each other exactly when they have the
+    /// same block here.
+    fn normalized(&self) -> Option<(BigInt, BigInt, RawBlock<'a, L>)> {
+        self.bounding_box().map(|bbox| {
+            let block = self.bbox_block(&bbox).to_raw();
+            (bbox.x, bbox.y, block)
+        })
+    }
+
+    /// North-west corner and size of a square containing the blocks of both
+    /// patterns.
+    fn common_frame(&self, other: &Self) -> (BigInt, BigInt, usize) {
+        let x = min(&self.x, &other.x).clone();
+        let y = min(&self.y, &other.y).clone();
+        let x_end = max(&self.x + self.side(), &other.x + other.side());
+        let y_end = max(&self.y + self.side(), &other.y + other.side());
+        let side = max(x_end - &x, y_end - &y).to_biguint().unwrap();
+        (x, y, log2_upper_bigu(&side) as usize)
+    }
+
+    /// Double the size of the block, keeping it centered.
+    fn encase(&mut self) {
+        let half = BigInt::one() << (self.block.lg_size() - 1);
         self.block = encase(self.hl(), self.block);
-        self.dead_space += 1 << lg_size;
+        self.x -= &half;
+        self.y -= &half;
     }
-*/
 
-    fn length_bigu(&self) -> BigUint {
-        (BigUint::one() << self.block.lg_size()) - (&self.dead_space << 1)
+    fn side(&self) -> BigInt {
+        BigInt::one() << self.block.lg_size()
     }
 
-    fn hl(&self) -> Hashlife<'a> {
+    fn hl(&self) -> Hashlife<'a, L> {
         self.block.hashlife_instance()
     }
 }
 
-impl<'a> Eq for Pattern<'a> { }
+impl<'a, L: LeafSize> Eq for Pattern<'a, L> { }
 
-impl<'a> PartialEq for Pattern<'a> {
+impl<'a, L: LeafSize> PartialEq for Pattern<'a, L> {
     fn eq(&self, other: &Self) -> bool {
-        use std::mem::swap;
+        let (x, y, lg_size) = self.common_frame(other);
+        self.block_at(&x, &y, lg_size) == other.block_at(&x, &y, lg_size)
+    }
+}
+
+impl<'a, L: LeafSize> Hash for Pattern<'a, L> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Equal patterns have the same bounding box, and the same block
+        // there. Node hashes are computed from the structure of the node, so
+        // they don't vary between runs.
+        self.normalized().hash(state);
+    }
+}
+
+/// If `seq` is periodic over its second half with period at most a quarter
+/// of its length, the least such period and the index from which `seq` is
+/// periodic with it.
+fn eventual_period<T: Eq>(seq: &[T]) -> Option<(u64, u64)> {
+    let len = seq.len();
+    let period = (1..=len / 4).find(|&p| (len / 2..len).all(|i|
+        seq[i] == seq[i - p]))?;
+    let start = (period..len).rev().find(|&i| seq[i] != seq[i - period])
+        .map_or(0, |i| i - period + 1);
+    Some((period as u64, start as u64))
+}
+
+/// Estimate how the population grows from the populations `pops` at
+/// successive generations. The increase from a quarter to half of the way
+/// through is compared to the increase over the second half, which is twice
+/// as large for linear growth and 4 times as large for quadratic growth. The
+/// populations are averaged over an eighth of the run to smooth out
+/// fluctuations, such as those from the period of a gun.
+fn population_growth(pops: &[BigUint]) -> Option<Growth> {
+    let len = pops.len();
+    if len < 8 {
+        return None;
+    }
+    let window = len / 8;
+    let mean = |end: usize| pops[end - window..end].iter()
+        .fold(BigUint::zero(), |sum, pop| sum + pop) / window;
+    let (quarter, half, last) = (mean(len / 4), mean(len / 2), mean(len));
+    if half <= quarter || last < half {
+        return None;
+    }
+    let (early, late) = (&half - quarter, last - half);
+    if late > &early * 3u8 {
+        Some(Growth::Superlinear)
+    } else if late >= early {
+        Some(Growth::Linear)
+    } else {
+        None
+    }
+}
+
+/// An arbitrary total order on blocks of the same size which depends only on
+/// their cells. Since nodes are hash-consed, equal subblocks are skipped
+/// immediately, so this only walks down a single path of the tree.
+fn structural_cmp<'a, L: LeafSize>(a: RawBlock<'a, L>, b: RawBlock<'a, L>) ->
+    Ordering {
 
-        let (mut a, mut b) = (self.block(), other.block());
-        if a.lg_size() > b.lg_size() {
-            swap(&mut a, &mut b);
+    if a == b {
+        return Ordering::Equal;
+    }
+    match (a, b) {
+        (RawBlock::Leaf(a), RawBlock::Leaf(b)) => a.cmp(&b),
+        (RawBlock::Node(na), RawBlock::Node(nb)) => {
+            let pairs = na.corners().iter().flatten()
+                .zip(nb.corners().iter().flatten());
+            for (&ca, &cb) in pairs {
+                if ca != cb {
+                    return structural_cmp(ca, cb);
+                }
+            }
+            Ordering::Equal
         }
-        while b.lg_size() > a.lg_size() {
-            a = encase(self.hl(), a);
+        _ => panic!("Comparing blocks of different sizes"),
+    }
+}
+
+/// Kill the cells of `block`, of size 2^lg_size, outside of the rectangle
+/// `[x0, x1) x [y0, y1)` (given as `[x0, y0, x1, y1]` relative to the block
+/// and within it) if `inside`, or the cells inside of it otherwise.
+// Nodes are hash-consed and hashed by structure, so their cells don't affect
+// their use as keys.
+#[allow(clippy::mutable_key_type)]
+fn crop<'a, L: LeafSize>(hl: &Hashlife<'a, L>, block: RawBlock<'a, L>,
+    lg_size: usize, bounds: [BigUint; 4], inside: bool, memo: &mut
+    HashMap<(RawBlock<'a, L>, [BigUint; 4]), RawBlock<'a, L>>) ->
+    RawBlock<'a, L> {
+
+    let size = BigUint::one() << lg_size;
+    let [x0, y0, x1, y1] = &bounds;
+    let empty = x0 >= x1 || y0 >= y1;
+    let full = x0.is_zero() && y0.is_zero() && *x1 == size && *y1 == size;
+    if block.is_blank() || (empty && !inside) || (full && inside) {
+        return block;
+    } else if (empty && inside) || (full && !inside) {
+        return hl.raw_blank(lg_size);
+    }
+
+    match block {
+        RawBlock::Leaf(l) => {
+            let n = |i: usize| bounds[i].to_usize().unwrap();
+            let mut mask = 0;
+            for y in n(1)..n(3) {
+                for x in n(0)..n(2) {
+                    mask |= 1 << (y*LEAF_Y_SHIFT + x*LEAF_X_SHIFT);
+                }
+            }
+            RawBlock::Leaf(if inside {l & mask} else {l & !mask})
+        }
+        RawBlock::Node(n) => {
+            let key = (block, bounds);
+            if let Some(&res) = memo.get(&key) {
+                return res;
+            }
+            let half: BigUint = &size >> 1;
+            let res = hl.raw_node_block(make_2x2(|i, j| {
+                let [x0, y0, x1, y1] = &key.1;
+                let (ox, oy) = (&half * j, &half * i);
+                let clip = |n: &BigUint, o: &BigUint| if n <= o {
+                    BigUint::zero()
+                } else {
+                    min(n - o, half.clone())
+                };
+                crop(hl, n.corners()[i][j], lg_size - 1, [clip(x0, &ox),
+                    clip(y0, &oy), clip(x1, &ox), clip(y1, &oy)], inside, memo)
+            }));
+            memo.insert(key, res);
+            res
         }
-        a == b
     }
 }
 
-fn encase<'a>(hl: Hashlife<'a>, b: Block<'a>) -> Block<'a> {
-    // Assumes b is a node.
-    let n = b.unwrap_node();
-    hl.node_block(make_2x2(|y0, x0| {
-        hl.node_block(make_2x2(|y1, x1| {
-            let x = 2*x0 + x1;
-            let y = 2*y0 + y1;
-            if 0 < x && x < 3 && 0 < y && y < 3 {
-                n.corners()[y-1][x-1]
-            } else {
-                hl.blank(b.lg_size() - 1)
+/// Return the 2^lg_size block whose north-west corner is (dx, dy) relative to
+/// that of `node`, which has size 2^node_lg_size. The returned block must be
+/// contained in `node`, and `node_lg_size` must be greater than `lg_size`.
+// Nodes are hash-consed and hashed by structure, so their cells don't affect
+// their use as keys.
+#[allow(clippy::mutable_key_type)]
+fn window<'a, L: LeafSize>(hl: &Hashlife<'a, L>, node: RawNode<'a, L>,
+    node_lg_size: usize, dx: BigUint, dy: BigUint, lg_size: usize, memo:
+    &mut HashMap<(RawNode<'a, L>, BigUint, BigUint), RawBlock<'a, L>>) ->
+    RawBlock<'a, L> {
+
+    if RawBlock::Node(node).is_blank() {
+        return hl.raw_blank(lg_size);
+    }
+
+    if node_lg_size > lg_size + 1 {
+        // Descend into one of the nine subblocks which contains the window.
+        let quarter = BigUint::one() << (node_lg_size - 2);
+        let sx = min(&dx / &quarter, BigUint::from_u8(2).unwrap());
+        let sy = min(&dy / &quarter, BigUint::from_u8(2).unwrap());
+        let sub = hl.raw_subblock(node, sy.to_u8().unwrap(),
+            sx.to_u8().unwrap());
+        return window(hl, *sub.unwrap_node(), node_lg_size - 1,
+            dx - sx * &quarter, dy - sy * &quarter, lg_size, memo);
+    }
+
+    let half = BigUint::one() << (lg_size - 1);
+    if (&dx % &half).is_zero() && (&dy % &half).is_zero() {
+        let sx = (&dx / &half).to_u8().unwrap();
+        let sy = (&dy / &half).to_u8().unwrap();
+        return hl.raw_subblock(node, sy, sx);
+    }
+
+    if node.node_of_leafs() {
+        let dx = dx.to_usize().unwrap();
+        let dy = dy.to_usize().unwrap();
+        let leafs = make_2x2(|i, j| node.corners()[i][j].unwrap_leaf());
+        let mut res = 0;
+        for y in 0..L::LEAF_SIZE {
+            for x in 0..L::LEAF_SIZE {
+                let (sx, sy) = (x + dx, y + dy);
+                let source = leafs[sy / L::LEAF_SIZE][sx / L::LEAF_SIZE];
+                let bit = (source >> ((sy % L::LEAF_SIZE) * LEAF_Y_SHIFT
+                    + (sx % L::LEAF_SIZE) * LEAF_X_SHIFT)) & 1;
+                res |= bit << (y * LEAF_Y_SHIFT + x * LEAF_X_SHIFT);
             }
-        }))
-    }))
-    // Leaf case (for later use):
-    //let shift = (1-y)*LEAF_SIZE*(LEAF_SIZE/2) + (1-x)*(LEAF_SIZE/2);
-    //let part = QUARTER_LEAF_MASK & (l >> shift);
-    //Block::Leaf(part << (y*LEAF_SIZE*(LEAF_SIZE/2) + x*(LEAF_SIZE/2)))
+        }
+        return RawBlock::Leaf(res);
+    }
+
+    let key = (node, dx, dy);
+    if let Some(&res) = memo.get(&key) {
+        return res;
+    }
+    let (_, dx, dy) = &key;
+    let corners = make_2x2(|qy, qx| {
+        let ox = dx + &half * qx;
+        let oy = dy + &half * qy;
+        let sx = min(&ox / &half, BigUint::from_u8(2).unwrap());
+        let sy = min(&oy / &half, BigUint::from_u8(2).unwrap());
+        let sub = hl.raw_subblock(node, sy.to_u8().unwrap(),
+            sx.to_u8().unwrap());
+        window(hl, *sub.unwrap_node(), lg_size, ox - sx * &half, oy - sy *
+            &half, lg_size - 1, memo)
+    });
+    let res = hl.raw_node_block(corners);
+    memo.insert(key, res);
+    res
+}
+
+fn encase<'a, L: LeafSize>(hl: Hashlife<'a, L>, b: Block<'a, L>) -> Block<'a,
+    L> {
+    match b.destruct() {
+        Ok(n) => hl.node_block(make_2x2(|y0, x0| {
+            hl.node_block(make_2x2(|y1, x1| {
+                let x = 2*x0 + x1;
+                let y = 2*y0 + y1;
+                if 0 < x && x < 3 && 0 < y && y < 3 {
+                    n.corners()[y-1][x-1]
+                } else {
+                    hl.blank(b.lg_size() - 1)
+                }
+            }))
+        })),
+        Err(l) => {
+            let half_leaf = L::LEAF_SIZE / 2;
+            hl.node_block(make_2x2(|y, x| {
+                let shift = y*half_leaf*LEAF_Y_SHIFT + x*half_leaf*LEAF_X_SHIFT;
+                let part = L::QUARTER_LEAF_MASK & (l >> shift);
+                hl.leaf(part << ((1-y)*half_leaf*LEAF_Y_SHIFT
+                    + (1-x)*half_leaf*LEAF_X_SHIFT))
+            }))
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Pattern;
-    use crate::Hashlife;
+    use num::{BigInt, BigUint, One, ToPrimitive, Zero};
+
+    use super::{Classification, Growth, Pattern, PasteMode, Rect, Spaceship};
+    use super::{eventual_period, population_growth};
+    use crate::{Hashlife, LeafSize, Symmetry};
+
+    fn parse<'a, L: LeafSize>(hl: Hashlife<'a, L>, bytes: &'static str) ->
+        Pattern<'a, L> {
 
-    fn parse<'a>(hl: Hashlife<'a>, bytes: &'static str) -> Pattern<'a> {
         Pattern::new(hl.rle(bytes))
     }
 
-    #[test]
-    fn test_blinker_1gen() {
-        Hashlife::with_new(|hl| {
+    fn blinker_1gen<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
             let mut blinker_in = parse(hl, "2$2b3o!");
             blinker_in.step(1);
             let blinker_out = parse(hl, "$3bo$3bo$3bob!");
             if blinker_in != blinker_out {
+                use crate::format::parse::PatternMetadata;
                 use crate::format::write::format_rle;
-                panic!("{}\n{}", format_rle(&blinker_in.block()),
-                    format_rle(&blinker_out.block()));
+                let metadata = PatternMetadata::default();
+                panic!("{}\n{}", format_rle(&blinker_in.block(), &metadata),
+                    format_rle(&blinker_out.block(), &metadata));
+            }
+        });
+    }
+
+    leaf_size_test!(test_blinker_1gen, blinker_1gen);
+
+    fn glider_moves<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            let glider = hl.rle("bo$2bo$3o!");
+            let mut pattern = Pattern::new(glider);
+            pattern.step(4);
+            let moved = Pattern::with_origin(glider, BigInt::one(),
+                BigInt::one());
+            assert_eq!(pattern, moved);
+            pattern.step(40);
+            let moved = Pattern::with_origin(glider, BigInt::from(11),
+                BigInt::from(11));
+            assert_eq!(pattern, moved);
+        });
+    }
+
+    leaf_size_test!(test_glider_moves, glider_moves);
+
+    #[test]
+    fn test_generation() {
+        Hashlife::with_new(|hl| {
+            let mut pattern = parse(hl, "bo$2bo$3o!");
+            assert_eq!(*pattern.generation(), BigUint::zero());
+            pattern.step(4);
+            pattern.step(0);
+            pattern.step_bigu(&BigUint::from(7u8));
+            assert_eq!(*pattern.generation(), BigUint::from(11u8));
+            let moved = pattern.translate(&BigInt::one(), &BigInt::one());
+            assert_eq!(*moved.generation(), BigUint::from(11u8));
+            let union = pattern.union(&parse(hl, "o!"));
+            assert_eq!(*union.generation(), BigUint::from(11u8));
+            // The generation doesn't affect equality.
+            pattern.set_generation(BigUint::zero());
+            assert_eq!(pattern, moved.translate(&-BigInt::one(),
+                &-BigInt::one()));
+        });
+    }
+
+    fn shrink<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            // A blinker that was encased many times.
+            let mut pattern = Pattern::new(hl.rle("2$2b3o!"));
+            let origin = pattern.origin();
+            let (x, y) = (origin.0.clone(), origin.1.clone());
+            for _ in 0..10 {
+                pattern.encase();
+            }
+            assert!(pattern.block().lg_size() > 10);
+            pattern.shrink();
+            assert!(pattern.block().lg_size() <= 3);
+            assert_eq!(pattern.origin(), (&x, &y));
+            assert_eq!(pattern, parse(hl, "2$2b3o!"));
+
+            // Cells in three different 8 by 8 columns of a 32 by 32 block
+            // still fit in a 16 by 16 block.
+            let pattern = parse(hl, "7$7bo8$16bo!");
+            assert_eq!(pattern.block().lg_size(), 4);
+            assert_eq!(pattern.origin(), (&BigInt::from(7), &BigInt::from(7)));
+
+            // Far apart cells die, leaving a blank pattern.
+            let mut cells = parse(hl, "o40bo$41bo!");
+            cells.step(1);
+            assert!(cells.block().is_blank());
+            assert_eq!(cells.block().lg_size(), L::LG_LEAF_SIZE + 1);
+            assert_eq!(cells, Pattern::new(hl.blank(4)));
+
+            // Two blocks, one of which is destroyed by a glider.
+            let mut blocks = parse(hl, "bo$2bo$3o3$4b2o$4b2o21$60b2o$60b2o!");
+            blocks.step(100);
+            assert_eq!(blocks, Pattern::with_origin(hl.rle("2o$2o!"),
+                BigInt::from(60), BigInt::from(27)));
+            assert!(blocks.block().lg_size() <= 3);
+        });
+    }
+
+    leaf_size_test!(test_shrink, shrink);
+
+    #[test]
+    fn test_shrink_after_step() {
+        Hashlife::with_new(|hl| {
+            let mut glider = parse(hl, "bo$2bo$3o!");
+            for _ in 0..10 {
+                glider.step(100);
+                assert!(glider.block().lg_size() <= 3);
+            }
+            assert_eq!(glider, Pattern::with_origin(hl.rle("bo$2bo$3o!"),
+                BigInt::from(250), BigInt::from(250)));
+        });
+    }
+
+    #[test]
+    fn test_population() {
+        Hashlife::with_new(|hl| {
+            let mut glider = parse(hl, "bo$2bo$3o!");
+            assert_eq!(glider.population(), BigUint::from(5u8));
+            glider.step(1001);
+            assert_eq!(glider.population(), BigUint::from(5u8));
+
+            // A glider gun emits a glider every 30 generations.
+            let mut gun = parse(hl, "24bo$22bobo$12b2o6b2o12b2o$11bo3bo4b2o12b\
+                2o$2o8bo5bo3b2o$2o8bo3bob2o4bobo$10bo5bo7bo$11bo3bo$12b2o!");
+            assert_eq!(gun.population(), BigUint::from(36u8));
+            gun.step(1 << 12);
+            assert!(gun.population() > BigUint::from(5u32 * (1 << 12) / 30));
+        });
+    }
+
+    fn run_hyper<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            let glider = hl.rle("bo$2bo$3o!");
+            let mut pattern = Pattern::new(glider);
+            let nsteps = (BigUint::one() << 20usize) + 4u8;
+            let mut generations = Vec::new();
+            pattern.run_hyper(&nsteps, 1000, |p| generations.push(
+                p.generation().clone()));
+            assert_eq!(*pattern.generation(), nsteps);
+            let dist = BigInt::from((1 << 18) + 1);
+            assert_eq!(pattern, Pattern::with_origin(glider, dist.clone(),
+                dist));
+            // Steps of 1, 2, 4, ..., 2^19, and then 5 generations remain.
+            assert_eq!(generations.len(), 22, "{:?}", generations);
+            assert_eq!(generations[1], BigUint::from(3u8));
+
+            let mut pattern = Pattern::new(glider);
+            let mut generations = Vec::new();
+            pattern.run_hyper(&BigUint::from(12u8), 1000, |p| generations.push(
+                p.generation().to_u8().unwrap()));
+            assert_eq!(generations, vec![1, 3, 7, 11, 12]);
+            assert_eq!(pattern, Pattern::with_origin(glider, BigInt::from(3),
+                BigInt::from(3)));
+        });
+    }
+
+    leaf_size_test!(test_run_hyper, run_hyper);
+
+    #[test]
+    fn test_run_hyper_growth() {
+        Hashlife::with_new(|hl| {
+            // The population of a glider gun grows linearly, by 5 cells per
+            // 30 generations.
+            let mut gun = parse(hl, "24bo$22bobo$12b2o6b2o12b2o$11bo3bo4b2o12b\
+                2o$2o8bo5bo3b2o$2o8bo3bob2o4bobo$10bo5bo7bo$11bo3bo$12b2o!");
+            let mut samples = Vec::new();
+            gun.run_hyper(&(BigUint::one() << 40usize), 10000, |p|
+                samples.push((p.generation().clone(), p.population())));
+            assert!(samples.len() < 100);
+            for (gen, pop) in samples {
+                if gen >= BigUint::from(1000u32) {
+                    let expected = &gen / 6u8;
+                    assert!(&pop + 50u8 > expected && pop < expected + 50u8,
+                        "{} {}", gen, pop);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        Hashlife::with_new(|hl| {
+            assert_eq!(parse(hl, "!").bounding_box(), None);
+            let mut glider = Pattern::with_origin(hl.rle("bo$2bo$3o!"),
+                BigInt::from(-10), BigInt::from(3));
+            let mut expected = Rect {
+                x: BigInt::from(-10),
+                y: BigInt::from(3),
+                width: BigUint::from(3u8),
+                height: BigUint::from(3u8),
+            };
+            assert_eq!(glider.bounding_box(), Some(expected.clone()));
+            glider.step(1);
+            expected.y += 1;
+            assert_eq!(glider.bounding_box(), Some(expected.clone()));
+            glider.step(1 << 30);
+            expected.x += 1 << 28;
+            expected.y += 1 << 28;
+            assert_eq!(glider.bounding_box(), Some(expected));
+
+            let cells = parse(hl, "5$10bo$4o!");
+            assert_eq!(cells.bounding_box(), Some(Rect {
+                x: BigInt::zero(),
+                y: BigInt::from(5),
+                width: BigUint::from(11u8),
+                height: BigUint::from(2u8),
+            }));
+        });
+    }
+
+    #[test]
+    fn test_get_cell() {
+        Hashlife::with_new(|hl| {
+            let glider = Pattern::with_origin(hl.rle("bo$2bo$3o!"),
+                BigInt::from(-1), BigInt::from(-1));
+            let mut cells = Vec::new();
+            for y in -20..20 {
+                for x in -20..20 {
+                    if glider.get_cell(&BigInt::from(x), &BigInt::from(y)) {
+                        cells.push((x, y));
+                    }
+                }
+            }
+            assert_eq!(cells, vec![(0, -1), (1, 0), (-1, 1), (0, 1), (1, 1)]);
+        });
+    }
+
+    fn set_cell<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            let mut pattern = parse(hl, "!");
+            let cells = [(0, -1), (1, 0), (-1, 1), (0, 1), (1, 1)];
+            for &(x, y) in &cells {
+                pattern.set_cell(&BigInt::from(x), &BigInt::from(y), true);
+            }
+            let glider = Pattern::with_origin(hl.rle("bo$2bo$3o!"),
+                BigInt::from(-1), BigInt::from(-1));
+            assert_eq!(pattern, glider);
+
+            // Far away cells
+            let far = BigInt::from(7) << 100;
+            pattern.set_cell(&far, &-&far, true);
+            assert!(pattern.get_cell(&far, &-&far));
+            assert_eq!(pattern.population(), BigUint::from(6u8));
+            assert!(pattern != glider);
+            pattern.set_cell(&far, &-&far, false);
+            assert_eq!(pattern, glider);
+            assert!(pattern.block().lg_size() <= 3);
+
+            for &(x, y) in &cells {
+                pattern.set_cell(&BigInt::from(x), &BigInt::from(y), false);
+            }
+            assert!(pattern.block().is_blank());
+        });
+    }
+
+    leaf_size_test!(test_set_cell, set_cell);
+
+    fn from_cells<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            let glider = Pattern::with_origin(hl.rle("bo$2bo$3o!"),
+                BigInt::from(-1), BigInt::from(-1));
+            let mut cells: Vec<(BigInt, BigInt)> = glider.live_cells()
+                .collect();
+            cells.sort_by(|a, b| (&a.1, &a.0).cmp(&(&b.1, &b.0)));
+            let c = |x: i32, y: i32| (BigInt::from(x), BigInt::from(y));
+            assert_eq!(cells, vec![c(0, -1), c(1, 0), c(-1, 1), c(0, 1),
+                c(1, 1)]);
+            assert_eq!(Pattern::from_cells(hl, cells.clone()), glider);
+
+            cells.push(c(0, -1));
+            let far: BigInt = BigInt::one() << 80usize;
+            cells.push((far.clone(), -&far));
+            let pattern = Pattern::from_cells(hl, cells);
+            assert_eq!(pattern.population(), BigUint::from(6u8));
+            assert!(pattern.get_cell(&far, &-&far));
+            assert_eq!(pattern.live_cells().count(), 6);
+
+            assert!(Pattern::from_cells(hl, Vec::new()).block().is_blank());
+        });
+    }
+
+    leaf_size_test!(test_from_cells, from_cells);
+
+    #[test]
+    fn test_bool_ops() {
+        Hashlife::with_new(|hl| {
+            let glider = Pattern::with_origin(hl.rle("bo$2bo$3o!"),
+                BigInt::from(-1), BigInt::from(-1));
+            let far: BigInt = BigInt::from(3) << 70usize;
+            let block = Pattern::with_origin(hl.rle("2o$2o!"), far.clone(),
+                far.clone());
+            let both = glider.union(&block);
+            assert_eq!(both.population(), BigUint::from(9u8));
+            assert_eq!(both.difference(&block), glider);
+            assert_eq!(both.intersection(&glider), glider);
+            assert_eq!(both.xor(&glider), block);
+
+            // Cells changed by a generation of the glider
+            let mut next = glider.clone();
+            next.step(1);
+            let changed = glider.xor(&next);
+            assert_eq!(changed, Pattern::from_cells(hl, vec![
+                (BigInt::from(0), BigInt::from(-1)),
+                (BigInt::from(-1), BigInt::from(0)),
+                (BigInt::from(-1), BigInt::from(1)),
+                (BigInt::from(0), BigInt::from(2)),
+            ]));
+        });
+    }
+
+    #[test]
+    fn test_transform() {
+        Hashlife::with_new(|hl| {
+            let glider = Pattern::with_origin(hl.rle("bo$2bo$3o!"),
+                BigInt::from(5), BigInt::from(-2));
+            let cells: Vec<(BigInt, BigInt)> = glider.live_cells().collect();
+            for &sym in &Symmetry::ALL {
+                let (swap, neg_x, neg_y) = sym.as_signed_permutation();
+                let expected = Pattern::from_cells(hl, cells.iter().map(|c| {
+                    let (x, y) = if swap {(&c.1, &c.0)} else {(&c.0, &c.1)};
+                    (if neg_x {-x} else {x.clone()},
+                     if neg_y {-y} else {y.clone()})
+                }));
+                assert_eq!(glider.transform(sym), expected, "{:?}", sym);
+            }
+
+            let rotated = glider.transform(Symmetry::Rotate90);
+            assert!(rotated.get_cell(&BigInt::from(2), &BigInt::from(6)));
+            assert_eq!(rotated.transform(Symmetry::Rotate270), glider);
+            assert_eq!(rotated.transform(Symmetry::Rotate90),
+                glider.transform(Symmetry::Rotate180));
+
+            // A glider moving south-east, reflected, moves south-west.
+            let mut flipped = glider.transform(Symmetry::FlipX);
+            flipped.step(4);
+            assert_eq!(flipped, glider.transform(Symmetry::FlipX).translate(
+                &BigInt::from(-1), &BigInt::one()));
+        });
+    }
+
+    fn extract_clear<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            let pattern = Pattern::with_origin(hl.rle("3o$3o$3o!"),
+                BigInt::from(-1), BigInt::from(-1));
+            let rect = Rect {
+                x: BigInt::zero(),
+                y: BigInt::from(-5),
+                width: BigUint::from(10u8),
+                height: BigUint::from(6u8),
+            };
+            let expected = Pattern::with_origin(hl.rle("2o$2o!"),
+                BigInt::zero(), BigInt::from(-1));
+            assert_eq!(pattern.extract(&rect), expected);
+
+            let mut cleared = pattern.clone();
+            cleared.clear(&rect);
+            assert_eq!(cleared, pattern.difference(&expected));
+            assert_eq!(cleared.population(), BigUint::from(5u8));
+
+            let all = pattern.bounding_box().unwrap();
+            assert_eq!(pattern.extract(&all), pattern);
+            cleared.clear(&all);
+            assert!(cleared.block().is_blank());
+        });
+    }
+
+    leaf_size_test!(test_extract_clear, extract_clear);
+
+    #[test]
+    fn test_extract_huge() {
+        Hashlife::with_new(|hl| {
+            // A huge square of blocks
+            let mut block = hl.rle("$b2o$b2o!");
+            for _ in 0..100 {
+                block = hl.node_block([[block; 2]; 2]);
+            }
+            let pattern = Pattern::new(block);
+            let big: BigInt = BigInt::one() << 90usize;
+            let rect = Rect {
+                x: big.clone() + 2,
+                y: big.clone(),
+                width: BigUint::one() << 80usize,
+                height: BigUint::from(4u8),
+            };
+            let row = pattern.extract(&rect);
+            // The rectangle cuts through the blocks at both ends.
+            assert_eq!(row.population(), BigUint::one() << 80usize);
+            assert_eq!(row.bounding_box(), Some(Rect {
+                x: big.clone() + 2,
+                y: big + 1,
+                width: BigUint::one() << 80usize,
+                height: BigUint::from(2u8),
+            }));
+        });
+    }
+
+    #[test]
+    fn test_paste() {
+        Hashlife::with_new(|hl| {
+            let glider = Pattern::new(hl.rle("bo$2bo$3o!"));
+            let block = Pattern::new(hl.rle("2o$2o!"));
+            let one = BigInt::one();
+
+            let mut or = glider.clone();
+            or.paste(&block, (&one, &one), PasteMode::Or);
+            assert_eq!(or, parse(hl, "bo$b2o$3o!"));
+
+            let mut xor = glider.clone();
+            xor.paste(&block, (&one, &one), PasteMode::Xor);
+            assert_eq!(xor, parse(hl, "bo$bo$o!"));
+
+            let mut and_not = glider.clone();
+            and_not.paste(&block, (&one, &one), PasteMode::AndNot);
+            assert_eq!(and_not, parse(hl, "bo2$o!"));
+
+            // Copying replaces the whole bounding box of the pasted pattern.
+            let mut copy = glider.clone();
+            copy.paste(&parse(hl, "o$2bo!"), (&BigInt::zero(), &one),
+                PasteMode::Copy);
+            assert_eq!(copy, parse(hl, "bo$o$2bo!"));
+        });
+    }
+
+    #[test]
+    fn test_eq_shifted() {
+        Hashlife::with_new(|hl| {
+            let shifted = parse(hl, "3bo$3bo$3bo!");
+            let line = hl.rle("o$o$o!");
+            assert_eq!(shifted, Pattern::with_origin(line, BigInt::from(3),
+                BigInt::zero()));
+            assert!(shifted != Pattern::with_origin(line, BigInt::from(2),
+                BigInt::zero()));
+            assert!(shifted != Pattern::new(line));
+        });
+    }
+
+    // Patterns are hashed by their cells, which the caches in their nodes
+    // don't affect.
+    #[allow(clippy::mutable_key_type)]
+    fn canonical<L: LeafSize>() {
+        use std::collections::HashSet;
+
+        Hashlife::<L>::with_new_leaf(|hl| {
+            let glider = Pattern::with_origin(hl.rle("$$3bo$4bo$2b3o!"),
+                BigInt::from(-7), BigInt::from(12));
+            let canonical = glider.canonical();
+            assert_eq!(canonical, parse(hl, "bo$2bo$3o!"));
+            assert_eq!(canonical.origin(), (&BigInt::zero(), &BigInt::zero()));
+            let far = BigInt::one() << 100usize;
+            assert_eq!(glider.translate(&far, &-&far).canonical(), canonical);
+            assert_eq!(parse(hl, "!").canonical(), parse(hl, "4$!"));
+
+            // A glider in each of its 4 phases and 4 directions, and at
+            // different positions, gives 16 different patterns up to
+            // translation, and 2 up to symmetry.
+            let mut patterns = Vec::new();
+            let mut phase = glider.clone();
+            for _ in 0..4 {
+                for &sym in &[Symmetry::Identity, Symmetry::Rotate90,
+                    Symmetry::Rotate180, Symmetry::Rotate270] {
+                    let moved = phase.transform(sym).translate(&BigInt::from(
+                        patterns.len()), &BigInt::zero());
+                    patterns.push(moved);
+                }
+                phase.step(1);
+            }
+            let distinct: HashSet<_> = patterns.iter().collect();
+            assert_eq!(distinct.len(), 16);
+            let translated: HashSet<_> = patterns.iter()
+                .map(|p| p.canonical()).collect();
+            assert_eq!(translated.len(), 16);
+            let symmetric: HashSet<_> = patterns.iter()
+                .map(|p| p.canonical_d4()).collect();
+            assert_eq!(symmetric.len(), 2);
+            for p in &patterns {
+                assert_eq!(p.transform(Symmetry::Transpose).canonical_d4(),
+                    p.canonical_d4());
             }
         });
     }
+
+    leaf_size_test!(test_canonical, canonical);
+
+    #[test]
+    fn test_hash() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        fn hash<T: Hash>(t: &T) -> u64 {
+            let mut s = DefaultHasher::new();
+            t.hash(&mut s);
+            s.finish()
+        }
+
+        Hashlife::with_new(|hl| {
+            // The same pattern with different blocks
+            let a = parse(hl, "bo$2bo$3o!");
+            let b = Pattern::with_origin(hl.rle("3$4bo$5bo$3b3o!"),
+                BigInt::from(-3), BigInt::from(-3));
+            let c = Pattern::with_origin(hl.node_block([[hl.blank(2),
+                hl.blank(2)], [hl.blank(2), hl.rle("bo$2bo$3o!")]]),
+                BigInt::from(-4), BigInt::from(-4));
+            assert_eq!(a, b);
+            assert_eq!(a, c);
+            assert_eq!(hash(&a), hash(&b));
+            assert_eq!(hash(&a), hash(&c));
+            assert!(hash(&a) != hash(&a.translate(&BigInt::one(),
+                &BigInt::zero())));
+        });
+    }
+
+    fn detect_period<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            assert_eq!(parse(hl, "!").detect_period(10), Some(1));
+            assert_eq!(parse(hl, "2o$2o!").detect_period(10), Some(1));
+            assert_eq!(parse(hl, "$3o!").detect_period(10), Some(2));
+            let pentadecathlon = parse(hl, "2bo4bo$2ob4ob2o$2bo4bo!");
+            assert_eq!(pentadecathlon.detect_period(100), Some(15));
+            assert_eq!(pentadecathlon.detect_period(14), None);
+            // Gliders come back translated, which isn't a period.
+            assert_eq!(parse(hl, "bo$2bo$3o!").detect_period(20), None);
+            // The R-pentomino doesn't stabilise for over 1000 generations.
+            assert_eq!(parse(hl, "b2o$2o$bo!").detect_period(50), None);
+        });
+    }
+
+    leaf_size_test!(test_detect_period, detect_period);
+
+    fn detect_spaceship<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            let glider = parse(hl, "bo$2bo$3o!");
+            let ship = glider.detect_spaceship(10).unwrap();
+            assert_eq!(ship, Spaceship {period: 4, dx: BigInt::one(), dy:
+                BigInt::one()});
+            assert_eq!(ship.speed(), "c/4 diagonal");
+            let ship = glider.transform(Symmetry::Rotate90)
+                .detect_spaceship(10).unwrap();
+            assert_eq!((ship.dx, ship.dy), (-BigInt::one(), BigInt::one()));
+            assert_eq!(glider.detect_spaceship(3), None);
+
+            let lwss = parse(hl, "bo2bo$o$o3bo$4o!");
+            let ship = lwss.detect_spaceship(10).unwrap();
+            assert_eq!(ship, Spaceship {period: 4, dx: BigInt::from(-2), dy:
+                BigInt::zero()});
+            assert_eq!(ship.speed(), "c/2 orthogonal");
+
+            // Oscillators aren't spaceships.
+            assert_eq!(parse(hl, "$3o!").detect_spaceship(10), None);
+            assert_eq!(parse(hl, "!").detect_spaceship(10), None);
+        });
+    }
+
+    leaf_size_test!(test_detect_spaceship, detect_spaceship);
+
+    #[test]
+    fn test_spaceship_speed() {
+        let speed = |period, dx: i32, dy: i32| Spaceship {period, dx:
+            BigInt::from(dx), dy: BigInt::from(dy)}.speed();
+        assert_eq!(speed(2, 0, -1), "c/2 orthogonal");
+        assert_eq!(speed(5, 2, 0), "2c/5 orthogonal");
+        assert_eq!(speed(12, -3, 3), "c/4 diagonal");
+        assert_eq!(speed(6, 1, -2), "(2,1)c/6");
+        assert_eq!(speed(12, 2, -4), "(2,1)c/6");
+        assert_eq!(speed(10, 0, 4), "2c/5 orthogonal");
+    }
+
+    fn classify<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            assert_eq!(parse(hl, "!").classify(10), Classification::Dies {
+                generation: 0});
+            assert_eq!(parse(hl, "o$bo!").classify(10), Classification::Dies {
+                generation: 1});
+            assert_eq!(parse(hl, "2o$o$2o!").classify(10), Classification::Dies {
+                generation: 4});
+            // The I-tetromino becomes a beehive, and the T-tetromino a
+            // traffic light.
+            assert_eq!(parse(hl, "4o!").classify(100),
+                Classification::Oscillator {period: 1, generation: 2});
+            assert_eq!(parse(hl, "3o$bo!").classify(100),
+                Classification::Oscillator {period: 2, generation: 9});
+            let ship = Spaceship {period: 4, dx: BigInt::one(), dy:
+                BigInt::one()};
+            assert_eq!(parse(hl, "bo$2bo$3o!").classify(100),
+                Classification::Spaceship {ship, generation: 0});
+
+            let gun = parse(hl, "24bo$22bobo$12b2o6b2o12b2o$11bo3bo4b2o12b\
+                2o$2o8bo5bo3b2o$2o8bo3bob2o4bobo$10bo5bo7bo$11bo3bo$12b2o!");
+            assert_eq!(gun.classify(400),
+                Classification::Grows(Growth::Linear));
+
+            // The R-pentomino is still evolving after 200 generations.
+            assert_eq!(parse(hl, "b2o$2o$bo!").classify(200),
+                Classification::Unknown);
+        });
+    }
+
+    leaf_size_test!(test_classify, classify);
+
+    #[test]
+    fn test_classify_emits_spaceships() {
+        Hashlife::with_new(|hl| {
+            // A glider flying away from a blinker. The population of both is
+            // constant.
+            let pattern = parse(hl, "3o$o$bo10$20b3o!");
+            assert_eq!(pattern.classify(100),
+                Classification::EmitsSpaceships {period: 1, generation: 0});
+        });
+    }
+
+    #[test]
+    fn test_eventual_period() {
+        assert_eq!(eventual_period(&[1, 2, 3, 4, 5, 6, 7, 8]), None);
+        assert_eq!(eventual_period(&[5, 5, 5, 5, 5, 5, 5, 5]), Some((1, 0)));
+        assert_eq!(eventual_period(&[9, 8, 1, 2, 1, 2, 1, 2, 1, 2]),
+            Some((2, 2)));
+        // The period is too long to be sure of
+        assert_eq!(eventual_period(&[0, 1, 2, 3, 4, 0, 1, 2, 3, 4]), None);
+        assert_eq!(eventual_period(&[0, 1, 2, 3, 1, 2, 3, 1, 2, 3, 1, 2, 3,
+            1]), Some((3, 1)));
+    }
+
+    #[test]
+    fn test_population_growth() {
+        let pops = |f: &dyn Fn(u32) -> u32| (0..100).map(|n| BigUint::from(
+            f(n))).collect::<Vec<_>>();
+        assert_eq!(population_growth(&pops(&|n| 10 + 3 * n)),
+            Some(Growth::Linear));
+        assert_eq!(population_growth(&pops(&|n| n * n)),
+            Some(Growth::Superlinear));
+        assert_eq!(population_growth(&pops(&|n| 100 + n % 7)), None);
+        assert_eq!(population_growth(&pops(&|_| 0)), None);
+    }
+
+    #[test]
+    fn test_block_at() {
+        Hashlife::with_new(|hl| {
+            let pattern = Pattern::with_origin(hl.rle("bo$2bo$3o!"),
+                BigInt::from(-5), BigInt::from(7));
+            assert_eq!(pattern.block_at(&BigInt::from(-5), &BigInt::from(7),
+                2), hl.rle("bo$2bo$3o$4b!"));
+            assert_eq!(pattern.block_at(&BigInt::from(-6), &BigInt::from(6),
+                3), hl.rle("$2bo$3bo$b3o$8b!"));
+            assert_eq!(pattern.block_at(&BigInt::from(-4), &BigInt::from(8),
+                1), hl.rle("bo$2o!"));
+            assert!(pattern.block_at(&BigInt::from(100), &BigInt::from(7), 4)
+                .is_blank());
+        });
+    }
 }
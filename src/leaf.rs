@@ -1,45 +1,90 @@
-/// Module for the leaf data structure in the block tree.
-///
-/// Leaf is the leaf data structure in a block. It is a type synonym for an integer type and stores
-/// a `LEAF_SIZE x LEAF_SIZE` block of cells as a bit vector. LEAF_SIZE is always a power of 2, and
-/// is equal to `2^LG_LEAF_SIZE`.  For each bit, 1 represents alive and 0 represents dead.  The bit
-/// representing the (x, y) coordinate is the `(y*LEAF_Y_SHIFT + x*LEAF_X_SHIFT)`th least
-/// significant bit. `LEAF_MASK` is a mask with all valid bits of the leaf set, and
-/// `QUARTER_LEAF_MASK` is a mask for the top left (LEAF_SIZE/2) x (LEAF_SIZE/2) subblock.
-///
-/// Currently there are two configurations for Leaf: LEAF_SIZE is 2 by default, but can be set to 4
-/// with the 4x4_leaf feature. With 4x4_leaf progressing a pattern is significantly more efficient,
-/// but not all features work with it yet.
-
-#[cfg(feature = "4x4_leaf")]
-pub use self::leaf_4x4::{Leaf, LG_LEAF_SIZE, LEAF_MASK, QUARTER_LEAF_MASK};
-#[cfg(not(feature = "4x4_leaf"))]
-pub use self::leaf_2x2::{Leaf, LG_LEAF_SIZE, LEAF_MASK, QUARTER_LEAF_MASK};
-
-/// Side length of `LEAF_SIZE`
-pub const LEAF_SIZE: usize = 1 << LG_LEAF_SIZE;
+//! Module for the leaf data structure in the block tree.
+//!
+//! Leaf is the leaf data structure in a block. It is a type synonym for an integer type and stores
+//! a `LEAF_SIZE x LEAF_SIZE` block of cells as a bit vector. LEAF_SIZE is always a power of 2, and
+//! is equal to `2^LG_LEAF_SIZE`.  For each bit, 1 represents alive and 0 represents dead.  The bit
+//! representing the (x, y) coordinate is the `(y*LEAF_Y_SHIFT + x*LEAF_X_SHIFT)`th least
+//! significant bit. `LEAF_MASK` is a mask with all valid bits of the leaf set, and
+//! `QUARTER_LEAF_MASK` is a mask for the top left (LEAF_SIZE/2) x (LEAF_SIZE/2) subblock.
+//!
+//! Currently there are two configurations for Leaf, selected by the `LeafSize` type parameter of
+//! `Hashlife`: `Leaf2x2` has LEAF_SIZE 2 and `Leaf4x4` has LEAF_SIZE 4. With `Leaf4x4` progressing
+//! a pattern is significantly more efficient, while `Leaf2x2` wastes less space on small and
+//! sparse patterns. Both use the same `Leaf` integer type, so only the interpretation of the bits
+//! differs.
+
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use crate::evolve;
+
+pub type Leaf = u16;
+
 pub const LEAF_Y_SHIFT: usize = 4;
 pub const LEAF_X_SHIFT: usize = 1;
 
-#[cfg(not(feature = "4x4_leaf"))]
-mod leaf_2x2 {
+/// The size of the leafs of a block tree, and the leaf-level evolution code
+/// that goes with it. Implemented by `Leaf2x2` and `Leaf4x4`.
+pub trait LeafSize: Copy + Debug + Eq + Hash + 'static {
+    const LG_LEAF_SIZE: usize;
+    /// Side length of a leaf
+    const LEAF_SIZE: usize = 1 << Self::LG_LEAF_SIZE;
+    const LEAF_MASK: Leaf;
+    // For global::encase
+    const QUARTER_LEAF_MASK: Leaf;
+
+    /// `evolve` specialized to when the corners are all leafs.
+    fn evolve_leaf(small_evolve_cache: &[u8; 1<<16], leafs: [[Leaf; 2]; 2]) ->
+        Leaf;
+
+    /// Return the central leaf of the 2x2 array of leafs `leafs` after it
+    /// evolved `nstep` steps. Requires `nstep < LEAF_SIZE/2`.
+    fn leaf_step(small_evolve_cache: &[u8; 1<<16], leafs: [[Leaf; 2]; 2],
+        nstep: u64) -> Leaf;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Leaf2x2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Leaf4x4;
+
+impl LeafSize for Leaf2x2 {
     // 01
     // 45
-    pub type Leaf = u8;
+    const LG_LEAF_SIZE: usize = 1;
+    const LEAF_MASK: Leaf = 0x33;
+    const QUARTER_LEAF_MASK: Leaf = 0x01;
 
-    pub const LG_LEAF_SIZE: usize = 1;
-    pub const LEAF_MASK: Leaf = 0x33;
+    #[inline]
+    fn evolve_leaf(small_evolve_cache: &[u8; 1<<16], leafs: [[Leaf; 2]; 2]) ->
+        Leaf {
 
-    // For global::encase
-    pub const QUARTER_LEAF_MASK: Leaf = 0x01;
+        evolve::evolve_leaf_2x2(small_evolve_cache, leafs)
+    }
+
+    fn leaf_step(small_evolve_cache: &[u8; 1<<16], leafs: [[Leaf; 2]; 2],
+        nstep: u64) -> Leaf {
+
+        evolve::leaf_step_2x2(small_evolve_cache, leafs, nstep)
+    }
 }
 
-#[cfg(feature = "4x4_leaf")]
-mod leaf_4x4 {
-    pub type Leaf = u16;
+impl LeafSize for Leaf4x4 {
+    const LG_LEAF_SIZE: usize = 2;
+    const LEAF_MASK: Leaf = 0xffff;
+    const QUARTER_LEAF_MASK: Leaf = 0x33;
+
+    #[inline]
+    fn evolve_leaf(small_evolve_cache: &[u8; 1<<16], leafs: [[Leaf; 2]; 2]) ->
+        Leaf {
+
+        evolve::evolve_leaf_4x4(small_evolve_cache, leafs)
+    }
 
-    pub const LG_LEAF_SIZE: usize = 2;
-    pub const LEAF_MASK: Leaf = 0xffff;
+    fn leaf_step(small_evolve_cache: &[u8; 1<<16], leafs: [[Leaf; 2]; 2],
+        nstep: u64) -> Leaf {
 
-    pub const QUARTER_LEAF_MASK: Leaf = 0x33;
+        evolve::leaf_step_4x4(small_evolve_cache, leafs, nstep)
+    }
 }
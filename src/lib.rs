@@ -14,19 +14,26 @@ mod util;
 pub mod evolve;
 pub mod format;
 pub mod global;
+pub mod census;
+pub mod soup;
 
 //pub use evolve::Hashlife;
 
 mod block;
 mod leaf;
 mod cache;
+mod boolean;
+mod transform;
 
 use std::cell::{RefCell, RefMut};
 use std::fmt;
 
-use num::{BigUint, One, FromPrimitive};
+use num::{BigUint, One, FromPrimitive, Zero};
 
-pub use crate::leaf::{Leaf, LG_LEAF_SIZE, LEAF_SIZE, LEAF_MASK};
+pub use crate::leaf::{Leaf, LeafSize, Leaf2x2, Leaf4x4};
+pub use crate::boolean::BoolOp;
+pub use crate::transform::Symmetry;
+use crate::leaf::{LEAF_Y_SHIFT, LEAF_X_SHIFT};
 use crate::block::{
     Block as RawBlock,
     Node as RawNode,
@@ -36,41 +43,54 @@ use crate::util::make_2x2;
 
 /// Global state for the Hashlife algorithm. For information on the lifetime
 /// parameter see `block::CABlockHash`.
-struct HashlifeCache<'a> {
-    table: RefCell<CABlockCache<'a>>,
+struct HashlifeCache<'a, L: LeafSize> {
+    table: RefCell<CABlockCache<'a, L>>,
     small_evolve_cache: [u8; 1<<16],
-    blank_cache: RefCell<Vec<RawBlock<'a>>>,
+    blank_cache: RefCell<Vec<RawBlock<'a, L>>>,
     //placeholder_node: Node<'a>,
 }
 
+/// Handle to the global state of a Hashlife computation. The type parameter
+/// `L` is the size of the leafs of the block tree (see `LeafSize`), and
+/// defaults to `Leaf2x2`.
 #[derive(Clone, Copy, Debug)]
-pub struct Hashlife<'a>(&'a HashlifeCache<'a>);
+pub struct Hashlife<'a, L: LeafSize = Leaf2x2>(&'a HashlifeCache<'a, L>);
 
 #[derive(Clone, Copy, Debug)]
-pub struct Block<'a> {
-    raw: RawBlock<'a>,
-    hl: Hashlife<'a>,
+pub struct Block<'a, L: LeafSize = Leaf2x2> {
+    raw: RawBlock<'a, L>,
+    hl: Hashlife<'a, L>,
     lg_size: usize,
 }
 
 #[derive(Clone, Copy, Debug)]
-pub struct Node<'a> {
-    raw: RawNode<'a>,
-    hl: Hashlife<'a>,
+pub struct Node<'a, L: LeafSize = Leaf2x2> {
+    raw: RawNode<'a, L>,
+    hl: Hashlife<'a, L>,
     lg_size: usize,
 }
 
-impl<'a> Drop for HashlifeCache<'a> {
+impl<'a, L: LeafSize> Drop for HashlifeCache<'a, L> {
     fn drop(&mut self) {
         self.blank_cache.get_mut().clear();
     }
 }
 
 impl<'a> Hashlife<'a> {
-    /// Create a new Hashlife and pass it to a function. For explanation on why
-    /// this function calling convention is used see `CABlockCache::with_new`
+    /// Create a new Hashlife with 2x2 leafs and pass it to a function. For
+    /// explanation on why this function calling convention is used see
+    /// `CABlockCache::with_new`
     pub fn with_new<F,T>(f: F) -> T
         where F: for<'b> FnOnce(Hashlife<'b>) -> T {
+        Hashlife::with_new_leaf(f)
+    }
+}
+
+impl<'a, L: LeafSize> Hashlife<'a, L> {
+    /// Like `with_new`, but with the leaf size given by the type parameter
+    /// `L`, as in `Hashlife::<Leaf4x4>::with_new_leaf(f)`.
+    pub fn with_new_leaf<F,T>(f: F) -> T
+        where F: for<'b> FnOnce(Hashlife<'b, L>) -> T {
         CABlockCache::with_new(|bcache| {
             //let placeholder_node = bcache.new_block([[Block::Leaf(0); 2]; 2]);
             let hashlife_cache = HashlifeCache {
@@ -85,12 +105,12 @@ impl<'a> Hashlife<'a> {
     }
 
     /// Create a new raw node with `elems` as corners
-    pub fn raw_node(&self, elems: [[RawBlock<'a>; 2]; 2]) -> RawNode<'a> {
+    pub fn raw_node(&self, elems: [[RawBlock<'a, L>; 2]; 2]) -> RawNode<'a, L> {
         self.block_cache().node(elems)
     }
 
     /// Creates a node `elems` as corners. Panics with sizes don't match.
-    pub fn node(&self, elems: [[Block<'a>; 2]; 2]) -> Node<'a> {
+    pub fn node(&self, elems: [[Block<'a, L>; 2]; 2]) -> Node<'a, L> {
         let elem_lg_size = elems[0][0].lg_size();
         make_2x2(|i, j| assert_eq!(elems[i][j].lg_size(), elem_lg_size,
             "Sizes don't match in new node"));
@@ -104,29 +124,29 @@ impl<'a> Hashlife<'a> {
     }
 
     /// Create a new block with `elems` as corners
-    pub fn raw_node_block(&self, elems: [[RawBlock<'a>; 2]; 2]) -> RawBlock<'a>
-    {
+    pub fn raw_node_block(&self, elems: [[RawBlock<'a, L>; 2]; 2]) ->
+        RawBlock<'a, L> {
         RawBlock::Node(self.raw_node(elems))
     }
 
     /// Creates a new block with `elems` as corners. Panics if sizes don't
     /// match.
-    pub fn node_block(&self, elems: [[Block<'a>; 2]; 2]) -> Block<'a> {
+    pub fn node_block(&self, elems: [[Block<'a, L>; 2]; 2]) -> Block<'a, L> {
         Block::from_node(self.node(elems))
     }
 
     /// Creates leaf block
-    pub fn leaf(&self, leaf: Leaf) -> Block<'a> {
+    pub fn leaf(&self, leaf: Leaf) -> Block<'a, L> {
         Block {
             raw: RawBlock::Leaf(leaf),
             hl: *self,
-            lg_size: LG_LEAF_SIZE,
+            lg_size: L::LG_LEAF_SIZE,
         }
     }
 
     /// Reference to underlying block cache (I don't remember why I made it
     /// public)
-    pub fn block_cache(&self) -> RefMut<CABlockCache<'a>> {
+    pub fn block_cache(&self) -> RefMut<'_, CABlockCache<'a, L>> {
         self.0.table.borrow_mut()
     }
 
@@ -140,8 +160,8 @@ impl<'a> Hashlife<'a> {
     /// Hashlife algorithm.
     ///
     /// This is the raw version of big stepping.
-    pub fn raw_evolve(&self, node: RawNode<'a>) -> RawBlock<'a> {
-        evolve::evolve(self, node, node.lg_size() - LG_LEAF_SIZE - 1)
+    pub fn raw_evolve(&self, node: RawNode<'a, L>) -> RawBlock<'a, L> {
+        evolve::evolve(self, node, node.lg_size() - L::LG_LEAF_SIZE - 1)
     }
 
     /// Given 2^(n+1)x2^(n+1) node `node`, progress it 2^(n-1) generations and
@@ -149,7 +169,7 @@ impl<'a> Hashlife<'a> {
     /// Hashlife algorithm.
     ///
     /// This is the normal version of big stepping.
-    pub fn big_step(&self, node: Node<'a>) -> Block<'a> {
+    pub fn big_step(&self, node: Node<'a, L>) -> Block<'a, L> {
         Block {
             raw: self.raw_evolve(node.to_raw()),
             hl: *self,
@@ -161,14 +181,14 @@ impl<'a> Hashlife<'a> {
     /// south and x*2^(n-1) east of the north-west corner.
     ///
     /// Public for use in other modules in this crate; don't rely on it.
-    pub fn raw_subblock(&self, node: RawNode<'a>, y: u8, x: u8) -> RawBlock<'a>
-    {
+    pub fn raw_subblock(&self, node: RawNode<'a, L>, y: u8, x: u8) ->
+        RawBlock<'a, L> {
        evolve::subblock(self, node, y, x)
     }
     
     /// Returns a raw blank block (all the cells are dead) with a given depth
-    pub fn raw_blank(&self, lg_size: usize) -> RawBlock<'a> {
-        let depth = lg_size - LG_LEAF_SIZE;
+    pub fn raw_blank(&self, lg_size: usize) -> RawBlock<'a, L> {
+        let depth = lg_size - L::LG_LEAF_SIZE;
         let mut blank_cache = self.0.blank_cache.borrow_mut();
 
         if depth < blank_cache.len() {
@@ -185,7 +205,7 @@ impl<'a> Hashlife<'a> {
     }
 
     /// Returns a blank block (all the cells are dead) with a given depth
-    pub fn blank(&self, lg_size: usize) -> Block<'a> {
+    pub fn blank(&self, lg_size: usize) -> Block<'a, L> {
         Block {
             raw: self.raw_blank(lg_size),
             hl: *self,
@@ -193,7 +213,7 @@ impl<'a> Hashlife<'a> {
         }
     }
 
-    fn block_from_raw(&self, raw: RawBlock<'a>) -> Block<'a> {
+    fn block_from_raw(&self, raw: RawBlock<'a, L>) -> Block<'a, L> {
         Block {
             raw: raw,
             hl: *self,
@@ -201,25 +221,27 @@ impl<'a> Hashlife<'a> {
         }
     }
 
-    fn node_from_raw(&self, raw: RawNode<'a>) -> Node<'a> {
-        Node {
-            raw: raw,
-            hl: *self,
-            lg_size: RawBlock::Node(raw).lg_size_verified().unwrap(),
-        }
+    /// Like `block_from_raw` but trusts that `raw` has size `2^lg_size`
+    /// instead of walking down the tree to verify it.
+    fn block_from_raw_sized(&self, raw: RawBlock<'a, L>, lg_size: usize) ->
+        Block<'a, L> {
+
+        debug_assert_eq!(raw.lg_size(), lg_size);
+        Block {raw, hl: *self, lg_size}
     }
 
     /// Return sidelength 2^(n-1) block at the center of node after it evolved
     /// for 2^lognsteps steps.
-    pub fn raw_step_pow2(&self, node: RawNode<'a>, lognsteps: usize) ->
-        RawBlock<'a> {
+    pub fn raw_step_pow2(&self, node: RawNode<'a, L>, lognsteps: usize) ->
+        RawBlock<'a, L> {
 
         evolve::step_pow2(self, node, lognsteps)
     }
 
     /// Return sidelength 2^(n-1) block at the center of node after it evolved
     /// for 2^lognsteps steps.
-    pub fn step_pow2(&self, node: Node<'a>, lognsteps: usize) -> Block<'a> { 
+    pub fn step_pow2(&self, node: Node<'a, L>, lognsteps: usize) -> Block<'a, L>
+    {
         assert!(lognsteps + 2 <= node.lg_size());
 
         let raw_node = self.raw_step_pow2(node.to_raw(), lognsteps);
@@ -232,17 +254,18 @@ impl<'a> Hashlife<'a> {
 
     /// Return sidelength 2^(n-1) block at the center of the node after it
     /// evolved `nstep` steps. Requires `nstep < 2**(n-2)`.
-    pub fn step(&self, node: Node<'a>, nstep: u64) -> Block<'a> {
+    pub fn step(&self, node: Node<'a, L>, nstep: u64) -> Block<'a, L> {
         self.step_bigu(node, &BigUint::from_u64(nstep).unwrap())
     }
 
     /// Return sidelength 2^(n-1) block at the center of the node after it
     /// evolved `nstep` steps. Requires `nstep < 2**(n-2)`.
-    pub fn step_bigu(&self, node: Node<'a>, nstep: &BigUint) -> Block<'a> {
+    pub fn step_bigu(&self, node: Node<'a, L>, nstep: &BigUint) -> Block<'a, L>
+    {
         assert!(*nstep < BigUint::one() << (node.lg_size() - 2));
 
         let raw = evolve::step_u(self, node.to_raw(), node.lg_size() -
-            LG_LEAF_SIZE - 1, nstep);
+            L::LG_LEAF_SIZE - 1, nstep);
         Block {
             raw: raw,
             hl: *self,
@@ -251,9 +274,10 @@ impl<'a> Hashlife<'a> {
     }
 
     /// Return a block with all cells set randomly of size `2 ** lg_size`.
-    pub fn random_block<R:rand::Rng>(&self, rng: &mut R, lg_size: usize) -> Block<'a> {
-        if lg_size == LG_LEAF_SIZE {
-            let leaf = rng.gen::<Leaf>() & LEAF_MASK;
+    pub fn random_block<R:rand::Rng>(&self, rng: &mut R, lg_size: usize) ->
+        Block<'a, L> {
+        if lg_size == L::LG_LEAF_SIZE {
+            let leaf = rng.gen::<Leaf>() & L::LEAF_MASK;
             self.leaf(leaf)
         } else {
             self.node_block(make_2x2(|_,_| self.random_block(rng, lg_size-1)))
@@ -261,27 +285,28 @@ impl<'a> Hashlife<'a> {
     }
 }
 
-impl<'a> fmt::Debug for HashlifeCache<'a> {
+impl<'a, L: LeafSize> fmt::Debug for HashlifeCache<'a, L> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "<Hashlife instance>")
     }
 }
 
-impl<'a> Node<'a> {
-    pub fn to_raw(&self) -> RawNode<'a> {
+impl<'a, L: LeafSize> Node<'a, L> {
+    pub fn to_raw(&self) -> RawNode<'a, L> {
         self.raw
     }
 
-    pub fn hashlife_instance(&self) -> Hashlife<'a> {
+    pub fn hashlife_instance(&self) -> Hashlife<'a, L> {
         self.hl
     }
 
-    pub fn evolve(&self) -> Block<'a> {
+    pub fn evolve(&self) -> Block<'a, L> {
         self.hl.block_from_raw(self.hl.raw_evolve(self.raw))
     }
 
-    pub fn corners(&self) -> [[Block<'a>; 2]; 2] {
-        make_2x2(|i, j| self.hl.block_from_raw(self.raw.corners()[i][j]))
+    pub fn corners(&self) -> [[Block<'a, L>; 2]; 2] {
+        make_2x2(|i, j| self.hl.block_from_raw_sized(self.raw.corners()[i][j],
+            self.lg_size - 1))
     }
 
     pub fn lg_size(&self) -> usize {
@@ -289,37 +314,46 @@ impl<'a> Node<'a> {
     }
 
     pub fn node_of_leafs(&self) -> bool {
-        self.lg_size == 1
+        self.lg_size == L::LG_LEAF_SIZE + 1
+    }
+
+    /// Number of live cells in the node. Memoised in each node.
+    pub fn population(&self) -> BigUint {
+        self.raw.population()
     }
 }
 
-impl<'a> PartialEq for Node<'a> {
+impl<'a, L: LeafSize> PartialEq for Node<'a, L> {
     fn eq(&self, other: &Self) -> bool {
         self.raw == other.raw
     }
 }
 
-impl<'a> Eq for Node<'a> {}
+impl<'a, L: LeafSize> Eq for Node<'a, L> {}
 
-impl<'a> Block<'a> {
-    pub fn to_raw(&self) -> RawBlock<'a> {
+impl<'a, L: LeafSize> Block<'a, L> {
+    pub fn to_raw(&self) -> RawBlock<'a, L> {
         self.raw
     }
 
-    pub fn hashlife_instance(&self) -> Hashlife<'a> {
+    pub fn hashlife_instance(&self) -> Hashlife<'a, L> {
         self.hl
     }
 
-    pub fn from_node(node: Node<'a>) -> Self {
+    pub fn from_node(node: Node<'a, L>) -> Self {
         Block {
             raw: RawBlock::Node(node.raw),
             hl: node.hl,
             lg_size: node.lg_size,
         }
     }
-    pub fn destruct(self) -> Result<Node<'a>, Leaf> {
+    pub fn destruct(self) -> Result<Node<'a, L>, Leaf> {
         match self.raw {
-            RawBlock::Node(n) => Ok(self.hl.node_from_raw(n)),
+            RawBlock::Node(n) => Ok(Node {
+                raw: n,
+                hl: self.hl,
+                lg_size: self.lg_size,
+            }),
             RawBlock::Leaf(l) => Err(l),
         }
     }
@@ -328,7 +362,7 @@ impl<'a> Block<'a> {
         self.destruct().unwrap_err()
     }
 
-    pub fn unwrap_node(self) -> Node<'a> {
+    pub fn unwrap_node(self) -> Node<'a, L> {
         self.destruct().unwrap()
     }
 
@@ -340,23 +374,87 @@ impl<'a> Block<'a> {
         Ok(self.lg_size())
     }
 
+    /// Number of live cells in the block. Memoised in each node.
+    pub fn population(&self) -> BigUint {
+        self.raw.population()
+    }
+
     pub fn is_blank(&self) -> bool {
         self.raw.is_blank()
     }
+
+    /// Iterator over the coordinates (x, y) of the live cells of the block,
+    /// relative to its north-west corner.
+    pub fn live_cells(&self) -> LiveCells<'a, L> {
+        LiveCells::new(self.raw, self.lg_size)
+    }
 }
 
-impl<'a> PartialEq for Block<'a> {
+/// Iterator over the live cells of a block, returned by `Block::live_cells`.
+/// Blank parts of the block are skipped, so this takes time proportional to
+/// the number of live cells times the depth of the block.
+pub struct LiveCells<'a, L: LeafSize> {
+    // Blocks yet to be visited, with their sizes and positions
+    stack: Vec<(RawBlock<'a, L>, usize, BigUint, BigUint)>,
+    // Remaining cells of the current leaf, in reverse order
+    leaf_cells: Vec<(BigUint, BigUint)>,
+}
+
+impl<'a, L: LeafSize> LiveCells<'a, L> {
+    fn new(raw: RawBlock<'a, L>, lg_size: usize) -> Self {
+        LiveCells {
+            stack: vec![(raw, lg_size, BigUint::zero(), BigUint::zero())],
+            leaf_cells: Vec::new(),
+        }
+    }
+}
+
+impl<'a, L: LeafSize> Iterator for LiveCells<'a, L> {
+    type Item = (BigUint, BigUint);
+
+    fn next(&mut self) -> Option<(BigUint, BigUint)> {
+        while self.leaf_cells.is_empty() {
+            let (block, lg_size, x, y) = self.stack.pop()?;
+            if block.is_blank() {
+                continue;
+            }
+            match block {
+                RawBlock::Leaf(l) => {
+                    for j in (0..L::LEAF_SIZE).rev() {
+                        for i in (0..L::LEAF_SIZE).rev() {
+                            if (l >> (j*LEAF_Y_SHIFT + i*LEAF_X_SHIFT)) & 1 == 1
+                            {
+                                self.leaf_cells.push((&x + i, &y + j));
+                            }
+                        }
+                    }
+                }
+                RawBlock::Node(n) => {
+                    let half = BigUint::one() << (lg_size - 1);
+                    for &(i, j) in &[(1, 1), (1, 0), (0, 1), (0, 0)] {
+                        let cx = if j == 0 {x.clone()} else {&x + &half};
+                        let cy = if i == 0 {y.clone()} else {&y + &half};
+                        self.stack.push((n.corners()[i][j], lg_size - 1, cx,
+                            cy));
+                    }
+                }
+            }
+        }
+        self.leaf_cells.pop()
+    }
+}
+
+impl<'a, L: LeafSize> PartialEq for Block<'a, L> {
     fn eq(&self, other: &Self) -> bool {
         self.raw == other.raw
     }
 }
 
-impl<'a> Eq for Block<'a> {}
+impl<'a, L: LeafSize> Eq for Block<'a, L> {}
 
 #[cfg(test)]
 mod test {
-    use super::Hashlife;
-    use crate::leaf::LG_LEAF_SIZE;
+    use super::{Hashlife, LeafSize};
     use crate::block::Block;
 
     #[test]
@@ -373,12 +471,43 @@ mod test {
         });
     }
 
-    #[test]
-    fn test_blank1() {
-        Hashlife::with_new(|hl| {
-            assert_eq!(hl.raw_blank(LG_LEAF_SIZE), Block::Leaf(0));
+    fn blank1<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            assert_eq!(hl.raw_blank(L::LG_LEAF_SIZE), Block::Leaf(0));
             assert_eq!(hl.raw_blank(4).lg_size(), 4);
             assert_eq!(hl.raw_blank(5).lg_size(), 5);
         });
     }
+
+    leaf_size_test!(test_blank1, blank1);
+
+    fn live_cells<L: LeafSize>() {
+        use num::BigUint;
+
+        Hashlife::<L>::with_new_leaf(|hl| {
+            let block = hl.rle("bo$2bo$3o!");
+            let mut cells: Vec<(BigUint, BigUint)> = block.live_cells()
+                .collect();
+            cells.sort_by(|a, b| (&a.1, &a.0).cmp(&(&b.1, &b.0)));
+            let n = |x: u8| BigUint::from(x);
+            assert_eq!(cells, vec![(n(1), n(0)), (n(2), n(1)), (n(0), n(2)),
+                (n(1), n(2)), (n(2), n(2))]);
+            assert_eq!(hl.blank(100).live_cells().next(), None);
+
+            // Sparse huge block
+            let mut big = block;
+            for _ in 0..100 {
+                let blank = hl.blank(big.lg_size());
+                big = hl.node_block([[blank, blank], [blank, big]]);
+            }
+            let offset = (BigUint::from(1u8) << (big.lg_size())) - (BigUint::
+                from(1u8) << block.lg_size());
+            let mut big_cells: Vec<(BigUint, BigUint)> = big.live_cells()
+                .map(|(x, y)| (x - &offset, y - &offset)).collect();
+            big_cells.sort_by(|a, b| (&a.1, &a.0).cmp(&(&b.1, &b.0)));
+            assert_eq!(big_cells, cells);
+        });
+    }
+
+    leaf_size_test!(test_live_cells, live_cells);
 }
@@ -0,0 +1,284 @@
+//! Searching random soups, like apgsearch: each soup is run until it
+//! stabilises and the objects it leaves behind are counted.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use num::{BigInt, Zero};
+use rand::{ChaChaRng, Rng, SeedableRng};
+
+use crate::{Block, Hashlife, LeafSize, Symmetry};
+use crate::census::PATHOLOGICAL;
+use crate::global::{Classification, Growth, Pattern};
+
+/// Side length of a soup
+pub const SOUP_SIZE: usize = 16;
+const LG_SOUP_SIZE: usize = 4;
+
+/// How soups which grow linearly are counted
+pub const LINEAR: &str = "zz_LINEAR";
+/// How soups which grow superlinearly are counted
+pub const EXPLOSIVE: &str = "zz_EXPLOSIVE";
+
+/// The symmetry imposed on soups.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SoupSymmetry {
+    /// No symmetry
+    C1,
+    /// Mirror symmetric left to right
+    D2,
+    /// Mirror symmetric left to right and top to bottom
+    D4,
+    /// Symmetric under all the symmetries of the square
+    D8,
+}
+
+impl FromStr for SoupSymmetry {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "C1" => Ok(SoupSymmetry::C1),
+            "D2" => Ok(SoupSymmetry::D2),
+            "D4" => Ok(SoupSymmetry::D4),
+            "D8" => Ok(SoupSymmetry::D8),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for SoupSymmetry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Settings of a soup search. Soups are numbered, and the soup with a given
+/// number only depends on the seed and the symmetry, so searches can be
+/// reproduced and interesting soups regenerated.
+#[derive(Clone, Debug)]
+pub struct SoupSearch {
+    pub seed: u64,
+    pub symmetry: SoupSymmetry,
+    /// Soups which don't stabilise in this many generations are counted as
+    /// `PATHOLOGICAL`, or as growing.
+    pub max_gens: u64,
+    /// Largest period of objects recognised in the census
+    pub max_period: u64,
+}
+
+/// Results of a soup search.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SoupReport {
+    pub soups: u64,
+    /// Number of each kind of object, by apgcode
+    pub census: HashMap<String, u64>,
+    /// Number of the first soup each kind of object was found in
+    pub samples: HashMap<String, u64>,
+}
+
+impl SoupSearch {
+    pub fn new(seed: u64, symmetry: SoupSymmetry) -> Self {
+        SoupSearch {
+            seed,
+            symmetry,
+            max_gens: 10000,
+            max_period: 60,
+        }
+    }
+
+    /// The soup number `index`, a `SOUP_SIZE` by `SOUP_SIZE` square with
+    /// its north-west corner at the origin. The random cells are drawn row
+    /// by row, so the soups are the same whatever the leaf size.
+    pub fn soup<'a, L: LeafSize>(&self, hl: Hashlife<'a, L>, index: u64) ->
+        Pattern<'a, L> {
+
+        let words = |n: u64| [n as u32, (n >> 32) as u32];
+        let [s0, s1] = words(self.seed);
+        let [i0, i1] = words(index);
+        let mut rng = ChaChaRng::from_seed(&[s0, s1, i0, i1]);
+        // D8 soups only use the cells on and below the diagonal, which are
+        // reflected to the other side.
+        let used = |x, y| match self.symmetry {
+            SoupSymmetry::D8 => x <= y,
+            _ => true,
+        };
+        let mut cells = Vec::new();
+        for y in 0..SOUP_SIZE {
+            let row: u16 = rng.gen();
+            for x in 0..SOUP_SIZE {
+                if row >> x & 1 != 0 && used(x, y) {
+                    cells.push((BigInt::from(x), BigInt::from(y)));
+                }
+            }
+        }
+        let random = Pattern::from_cells(hl, cells).block_at(&BigInt::zero(),
+            &BigInt::zero(), LG_SOUP_SIZE);
+        let soup = match self.symmetry {
+            SoupSymmetry::C1 => random,
+            SoupSymmetry::D2 => {
+                let [[nw, _], [sw, _]] = random.unwrap_node().corners();
+                hl.node_block([[nw, nw.transform(Symmetry::FlipX)],
+                    [sw, sw.transform(Symmetry::FlipX)]])
+            }
+            SoupSymmetry::D4 => quadrants(hl, random.unwrap_node().corners()
+                [0][0]),
+            SoupSymmetry::D8 => {
+                let below = random.unwrap_node().corners()[0][0];
+                quadrants(hl, below.union(&below.transform(
+                    Symmetry::Transpose)))
+            }
+        };
+        Pattern::new(soup)
+    }
+
+    /// Run soup number `index` until it stabilises and count the objects it
+    /// leaves behind. Soups which don't stabilise are counted as a single
+    /// `LINEAR`, `EXPLOSIVE` or `PATHOLOGICAL` object.
+    pub fn run_soup<L: LeafSize>(&self, hl: Hashlife<L>, index: u64) ->
+        HashMap<String, u64> {
+
+        let mut soup = self.soup(hl, index);
+        let generation = match soup.classify(self.max_gens) {
+            Classification::Dies {..} => return HashMap::new(),
+            Classification::Oscillator {generation, ..}
+                | Classification::Spaceship {generation, ..} => generation,
+            // The population only became periodic at `generation`, so give
+            // any spaceships time to fly away from the rest.
+            Classification::EmitsSpaceships {..} => self.max_gens,
+            Classification::Grows(growth) => {
+                let code = match growth {
+                    Growth::Linear => LINEAR,
+                    Growth::Superlinear => EXPLOSIVE,
+                };
+                return vec![(code.to_string(), 1)].into_iter().collect();
+            }
+            Classification::Unknown =>
+                return vec![(PATHOLOGICAL.to_string(), 1)].into_iter()
+                    .collect(),
+        };
+        soup.step(generation);
+        soup.census(self.max_period)
+    }
+
+    /// Search the soups numbered `0..count`.
+    pub fn run<L: LeafSize>(&self, hl: Hashlife<L>, count: u64) -> SoupReport {
+        let mut report = SoupReport::default();
+        for index in 0..count {
+            for (code, n) in self.run_soup(hl, index) {
+                *report.census.entry(code.clone()).or_insert(0) += n;
+                report.samples.entry(code).or_insert(index);
+            }
+            report.soups += 1;
+        }
+        report
+    }
+}
+
+/// The block made of `nw` and its reflections in the other three quadrants.
+fn quadrants<'a, L: LeafSize>(hl: Hashlife<'a, L>, nw: Block<'a, L>) ->
+    Block<'a, L> {
+
+    hl.node_block([[nw, nw.transform(Symmetry::FlipX)],
+        [nw.transform(Symmetry::FlipY), nw.transform(Symmetry::Rotate180)]])
+}
+
+impl fmt::Display for SoupReport {
+    /// The census with the most common objects first, and the first soup
+    /// each was found in.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Soups: {}", self.soups)?;
+        let mut census: Vec<_> = self.census.iter().collect();
+        census.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (code, count) in census {
+            writeln!(f, "{} {} (soup {})", code, count, self.samples[code])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use num::BigInt;
+
+    use super::{SoupReport, SoupSearch, SoupSymmetry, SOUP_SIZE};
+    use crate::{Hashlife, LeafSize, Leaf2x2, Leaf4x4, Symmetry};
+    use crate::global::Rect;
+
+    fn soups<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            for &symmetry in &[SoupSymmetry::C1, SoupSymmetry::D2,
+                SoupSymmetry::D4, SoupSymmetry::D8] {
+                let search = SoupSearch::new(17, symmetry);
+                let soup = search.soup(hl, 3);
+                assert_eq!(soup, search.soup(hl, 3));
+                assert!(soup != search.soup(hl, 4));
+                assert!(soup != SoupSearch::new(18, symmetry).soup(hl, 3));
+
+                let bbox = soup.bounding_box().unwrap();
+                let square = Rect {x: 0.into(), y: 0.into(), width:
+                    SOUP_SIZE.into(), height: SOUP_SIZE.into()};
+                assert_eq!(soup.extract(&square), soup);
+                assert!(bbox.width <= SOUP_SIZE.into());
+
+                // Reflections in an axis map the square [0, 16) onto
+                // [-15, 1).
+                let side = SOUP_SIZE - 1;
+                let symmetric = |sym: Symmetry, dx: usize, dy: usize| soup
+                    .transform(sym).translate(&dx.into(), &dy.into()) == soup;
+                let expected = match symmetry {
+                    SoupSymmetry::C1 => [false, false, false],
+                    SoupSymmetry::D2 => [true, false, false],
+                    SoupSymmetry::D4 => [true, true, false],
+                    SoupSymmetry::D8 => [true, true, true],
+                };
+                assert_eq!([symmetric(Symmetry::FlipX, side, 0),
+                    symmetric(Symmetry::FlipY, 0, side),
+                    symmetric(Symmetry::Transpose, 0, 0)],
+                    expected, "{}", symmetry);
+            }
+        });
+    }
+
+    leaf_size_test!(test_soups, soups);
+
+    #[test]
+    fn test_soups_leaf_size() {
+        fn cells<L: LeafSize>(search: &SoupSearch) -> Vec<(BigInt, BigInt)> {
+            Hashlife::<L>::with_new_leaf(|hl| {
+                let mut cells: Vec<_> = search.soup(hl, 5).live_cells()
+                    .collect();
+                cells.sort();
+                cells
+            })
+        }
+
+        for &symmetry in &[SoupSymmetry::C1, SoupSymmetry::D8] {
+            let search = SoupSearch::new(17, symmetry);
+            assert!(!cells::<Leaf2x2>(&search).is_empty());
+            assert_eq!(cells::<Leaf2x2>(&search), cells::<Leaf4x4>(&search));
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        Hashlife::with_new(|hl| {
+            let mut search = SoupSearch::new(36, SoupSymmetry::C1);
+            search.max_gens = 2000;
+            search.max_period = 4;
+            let report = search.run(hl, 3);
+            assert_eq!(report.soups, 3);
+            assert!(!report.census.is_empty());
+            for (code, &soup) in &report.samples {
+                assert!(soup < 3);
+                assert!(search.run_soup(hl, soup).contains_key(code));
+            }
+            // Searches are reproducible.
+            let again = Hashlife::with_new(|hl| search.run(hl, 3));
+            assert_eq!(report, again);
+            assert_eq!(format!("{}", report), format!("{}", again));
+            assert!(format!("{}", SoupReport::default()) == "Soups: 0\n");
+        });
+    }
+}
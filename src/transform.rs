@@ -0,0 +1,161 @@
+//! The symmetries of the square grid which fix a point, applied to blocks.
+
+use std::collections::HashMap;
+
+use crate::{Block, Hashlife, Leaf, LeafSize};
+use crate::block::Block as RawBlock;
+use crate::leaf::{LEAF_Y_SHIFT, LEAF_X_SHIFT};
+
+/// One of the eight symmetries of the square (the dihedral group D4). The
+/// rotations are clockwise as seen with y increasing downwards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Symmetry {
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Mirror left to right
+    FlipX,
+    /// Mirror top to bottom
+    FlipY,
+    /// Mirror along the diagonal from the north-west to the south-east
+    Transpose,
+    /// Mirror along the diagonal from the north-east to the south-west
+    AntiTranspose,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity,
+        Symmetry::Rotate90,
+        Symmetry::Rotate180,
+        Symmetry::Rotate270,
+        Symmetry::FlipX,
+        Symmetry::FlipY,
+        Symmetry::Transpose,
+        Symmetry::AntiTranspose,
+    ];
+
+    /// The symmetry as the map (x, y) -> (x', y') obtained by first swapping
+    /// x and y if `swap`, and then negating x if `neg_x` and y if `neg_y`.
+    /// Returns `(swap, neg_x, neg_y)`.
+    pub fn as_signed_permutation(self) -> (bool, bool, bool) {
+        match self {
+            Symmetry::Identity => (false, false, false),
+            Symmetry::Rotate90 => (true, true, false),
+            Symmetry::Rotate180 => (false, true, true),
+            Symmetry::Rotate270 => (true, false, true),
+            Symmetry::FlipX => (false, true, false),
+            Symmetry::FlipY => (false, false, true),
+            Symmetry::Transpose => (true, false, false),
+            Symmetry::AntiTranspose => (true, true, true),
+        }
+    }
+
+    /// Where the cell at (x, y) of a square with side length `size` goes.
+    fn map_in_square(self, x: usize, y: usize, size: usize) -> (usize,
+        usize) {
+
+        let (swap, neg_x, neg_y) = self.as_signed_permutation();
+        let (x, y) = if swap {(y, x)} else {(x, y)};
+        (if neg_x {size - 1 - x} else {x}, if neg_y {size - 1 - y} else {y})
+    }
+
+    fn leaf<L: LeafSize>(self, leaf: Leaf) -> Leaf {
+        let mut res = 0;
+        for y in 0..L::LEAF_SIZE {
+            for x in 0..L::LEAF_SIZE {
+                let (nx, ny) = self.map_in_square(x, y, L::LEAF_SIZE);
+                let bit = (leaf >> (y*LEAF_Y_SHIFT + x*LEAF_X_SHIFT)) & 1;
+                res |= bit << (ny*LEAF_Y_SHIFT + nx*LEAF_X_SHIFT);
+            }
+        }
+        res
+    }
+}
+
+// Nodes are hash-consed and hashed by structure, so their cells don't affect
+// their use as keys.
+#[allow(clippy::mutable_key_type)]
+fn raw_transform<'a, L: LeafSize>(hl: &Hashlife<'a, L>, sym: Symmetry, block:
+    RawBlock<'a, L>, memo: &mut HashMap<RawBlock<'a, L>, RawBlock<'a, L>>) ->
+    RawBlock<'a, L> {
+
+    if block.is_blank() {
+        return block;
+    }
+    match block {
+        RawBlock::Leaf(l) => RawBlock::Leaf(sym.leaf::<L>(l)),
+        RawBlock::Node(n) => {
+            if let Some(&res) = memo.get(&block) {
+                return res;
+            }
+            let mut corners = *n.corners();
+            for y in 0..2 {
+                for x in 0..2 {
+                    let (nx, ny) = sym.map_in_square(x, y, 2);
+                    corners[ny][nx] = raw_transform(hl, sym, n.corners()[y][x],
+                        memo);
+                }
+            }
+            let res = hl.raw_node_block(corners);
+            memo.insert(block, res);
+            res
+        }
+    }
+}
+
+impl<'a, L: LeafSize> Block<'a, L> {
+    /// Apply the symmetry `sym` to the block, mapping it onto the same
+    /// square. Memoised for each distinct subblock.
+    pub fn transform(&self, sym: Symmetry) -> Block<'a, L> {
+        let hl = self.hashlife_instance();
+        let raw = raw_transform(&hl, sym, self.to_raw(), &mut HashMap::new());
+        hl.block_from_raw_sized(raw, self.lg_size())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Symmetry;
+    use crate::{Hashlife, LeafSize};
+
+    fn transform<L: LeafSize>() {
+        Hashlife::<L>::with_new_leaf(|hl| {
+            // An asymmetric pattern
+            let b = hl.rle("3o$o$bo!");
+            let expected = [
+                (Symmetry::Identity, "3o$o$bo!"),
+                (Symmetry::Rotate90, "2b2o$bobo$3bo!"),
+                (Symmetry::Rotate180, "$2bo$3bo$b3o!"),
+                (Symmetry::Rotate270, "$o$obo$2o!"),
+                (Symmetry::FlipX, "b3o$3bo$2bo!"),
+                (Symmetry::FlipY, "$bo$o$3o!"),
+                (Symmetry::Transpose, "2o$obo$o!"),
+                (Symmetry::AntiTranspose, "$3bo$bobo$2b2o!"),
+            ];
+            for &(sym, rle) in &expected {
+                let res = b.transform(sym);
+                assert_eq!(res, hl.rle(rle), "{:?}", sym);
+            }
+        });
+    }
+
+    leaf_size_test!(test_transform, transform);
+
+    #[test]
+    fn test_transform_huge() {
+        Hashlife::with_new(|hl| {
+            let b = hl.rle("3o$o$bo!");
+            let blank = hl.blank(2);
+            let mut big = hl.node_block([[b, blank], [blank, blank]]);
+            let mut expected = hl.node_block([[blank, b.transform(
+                Symmetry::FlipX)], [blank, blank]]);
+            for _ in 0..100 {
+                big = hl.node_block([[big; 2]; 2]);
+                expected = hl.node_block([[expected; 2]; 2]);
+            }
+            assert_eq!(big.transform(Symmetry::FlipX), expected);
+        });
+    }
+}
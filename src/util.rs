@@ -7,6 +7,19 @@ macro_rules! debug {
     }
 }
 
+/// Define a test named `$test` which runs the generic function `$body` with
+/// each leaf size.
+#[cfg(test)]
+macro_rules! leaf_size_test {
+    ($test:ident, $body:ident) => {
+        #[test]
+        fn $test() {
+            $body::<$crate::Leaf2x2>();
+            $body::<$crate::Leaf4x4>();
+        }
+    }
+}
+
 /*
 /// Return ceiling(log_2 (n))
 pub fn log2_upper(n: u64) -> u32 {